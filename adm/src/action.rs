@@ -0,0 +1,182 @@
+//! Actions: configured requests to be carried out by automations.
+
+use std::fmt;
+
+use crate::{message::Message, parse};
+
+/// A request to be carried out when an automation fires.
+///
+/// Actions either activate a scene or change the state of a device specifier (a device, group, or
+/// list thereof, as accepted by [`Config::resolve`](crate::config::Config::resolve)).
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Action {
+    /// The devices to act on.
+    pub device: Option<String>,
+    /// The scene to activate.
+    pub scene: Option<String>,
+    /// The power state to set.
+    pub power: Option<bool>,
+    /// Whether to toggle the devices' power.
+    pub toggle: Option<bool>,
+    /// The color to set (e.g. `red` or `kelvin:2700`).
+    pub color: Option<String>,
+    /// The brightness to set, in `[0, 1]`.
+    pub brightness: Option<f32>,
+    /// How long to fade the change over (e.g. `5s`).
+    pub duration: Option<String>,
+}
+
+/// Represents an error in the definition of an action.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Neither a device nor a scene was given.
+    NoTarget,
+    /// Both a device and a scene were given.
+    Ambiguous,
+    /// A device was given, but nothing to do with it.
+    Empty,
+    /// The color couldn't be parsed.
+    InvalidColor(String),
+    /// The brightness was out of range.
+    InvalidBrightness(f32),
+    /// The duration couldn't be parsed.
+    InvalidDuration(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            NoTarget => write!(f, "No device or scene given"),
+            Ambiguous => write!(f, "Both a device and a scene given"),
+            Empty => write!(
+                f,
+                "Nothing to do (use power, toggle, color, and/or brightness)"
+            ),
+            InvalidColor(color) => write!(f, "Unrecognized color {}", color),
+            InvalidBrightness(brightness) => write!(
+                f,
+                "Brightness {} out of range (expected 0 to 1)",
+                brightness
+            ),
+            InvalidDuration(duration) => write!(f, "Unrecognized duration {}", duration),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Action {
+    /// The message which carries out the action.
+    pub fn message(&self) -> Result<Message, Error> {
+        let device = match (&self.device, &self.scene) {
+            (Some(_), Some(_)) => return Err(Error::Ambiguous),
+            (None, Some(name)) => return Ok(Message::Scene { name: name.clone() }),
+            (None, None) => return Err(Error::NoTarget),
+            (Some(device), None) => device.clone(),
+        };
+        let color = match &self.color {
+            Some(c) => Some(parse::lifx_color(c).ok_or_else(|| Error::InvalidColor(c.clone()))?),
+            None => None,
+        };
+        if let Some(b) = self.brightness.filter(|b| !(0.0..=1.0).contains(b)) {
+            return Err(Error::InvalidBrightness(b));
+        }
+        let duration = match &self.duration {
+            Some(d) => Some(parse::duration(d).ok_or_else(|| Error::InvalidDuration(d.clone()))?),
+            None => None,
+        };
+        let message = match (self.toggle, self.power, color, self.brightness) {
+            (Some(true), ..) => Message::Toggle { device, duration },
+            (_, Some(false), ..) => Message::Power {
+                device,
+                power: false,
+                duration,
+            },
+            (_, power, None, None) => match power {
+                Some(true) => Message::Power {
+                    device,
+                    power: true,
+                    duration,
+                },
+                _ => return Err(Error::Empty),
+            },
+            (_, _, color, brightness) => Message::State {
+                device,
+                color,
+                brightness,
+                duration,
+            },
+        };
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn action(s: &str) -> Action {
+        toml::from_str(s).expect("Failed to parse action.")
+    }
+    #[test]
+    fn messages() {
+        match action("scene = \"movie\"").message() {
+            Ok(Message::Scene { name }) => assert_eq!(name, "movie"),
+            m => panic!("Unexpected message {:?}", m),
+        }
+        match action("device = \"porch\"\npower = true").message() {
+            Ok(Message::Power { device, power, .. }) => {
+                assert_eq!(device, "porch");
+                assert!(power);
+            }
+            m => panic!("Unexpected message {:?}", m),
+        }
+        match action("device = \"porch\"\ntoggle = true\npower = false\nduration = \"2s\"")
+            .message()
+        {
+            Ok(Message::Toggle { duration, .. }) => {
+                assert_eq!(duration, Some(std::time::Duration::from_secs(2)))
+            }
+            m => panic!("Unexpected message {:?}", m),
+        }
+        match action("device = \"porch\"\npower = true\nbrightness = 0.5").message() {
+            Ok(Message::State {
+                color, brightness, ..
+            }) => {
+                assert!(color.is_none());
+                assert_eq!(brightness, Some(0.5));
+            }
+            m => panic!("Unexpected message {:?}", m),
+        }
+        assert_eq!(action("").message().err(), Some(Error::NoTarget));
+        assert_eq!(
+            action("device = \"porch\"\nscene = \"movie\"")
+                .message()
+                .err(),
+            Some(Error::Ambiguous)
+        );
+        assert_eq!(
+            action("device = \"porch\"").message().err(),
+            Some(Error::Empty)
+        );
+        assert_eq!(
+            action("device = \"porch\"\ncolor = \"plaid\"")
+                .message()
+                .err(),
+            Some(Error::InvalidColor("plaid".to_string()))
+        );
+        assert_eq!(
+            action("device = \"porch\"\nbrightness = 50.0")
+                .message()
+                .err(),
+            Some(Error::InvalidBrightness(50.0))
+        );
+        assert_eq!(
+            action("device = \"porch\"\npower = true\nduration = \"soon\"")
+                .message()
+                .err(),
+            Some(Error::InvalidDuration("soon".to_string()))
+        );
+    }
+}
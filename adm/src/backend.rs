@@ -0,0 +1,477 @@
+//! Device backends.
+//!
+//! Each family of devices implements [`Backend`], and [`Device`](crate::device::Device) dispatches
+//! through it, so adding a new device type doesn't require touching every device method.
+
+use std::{
+    fmt, io, thread,
+    time::{Duration, SystemTime},
+};
+
+use lifxi::http::prelude::*;
+
+use crate::{
+    device::{Delta, DeviceState},
+    effect::{Effect, Waveform},
+    parse,
+};
+
+/// The result of a backend operation.
+pub type Result<T = ()> = std::result::Result<T, Error>;
+
+/// Represents an error encountered while talking to a device.
+#[derive(Debug)]
+pub enum Error {
+    /// The LIFX HTTP client encountered an error.
+    Lifx(lifxi::http::Error),
+    /// An I/O error occurred while talking to a device on the local network.
+    Io(io::Error),
+    /// The device didn't respond.
+    NoResponse,
+    /// The device address couldn't be parsed.
+    InvalidAddress(String),
+    /// The requested color can't be represented by the device's backend.
+    UnsupportedColor,
+    /// The requested effect isn't supported by the device's backend.
+    UnsupportedEffect(&'static str),
+    /// The device's response couldn't be understood.
+    Malformed(String),
+    /// A LIFX HTTP device was used without configuring a LIFX secret.
+    NoLifxSecret,
+}
+
+impl From<lifxi::http::Error> for Error {
+    fn from(err: lifxi::http::Error) -> Self {
+        Error::Lifx(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            Lifx(err) => write!(f, "lifxi error: {}", err),
+            Io(err) => write!(f, "I/O error: {}", err),
+            NoResponse => write!(f, "The device did not respond"),
+            InvalidAddress(address) => write!(f, "Invalid device address: {}", address),
+            UnsupportedColor => write!(f, "Unsupported color"),
+            UnsupportedEffect(effect) => write!(f, "Unsupported effect: {}", effect),
+            Malformed(err) => write!(f, "Malformed response: {}", err),
+            NoLifxSecret => write!(f, "LIFX devices used without configuring a LIFX secret"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The operations every device family must support.
+pub trait Backend {
+    /// Changes the power state of the device, fading over the given duration.
+    fn power(&self, on: bool, duration: Option<Duration>, fast: bool) -> Result;
+    /// Toggles the device, fading over the given duration.
+    fn toggle(&self, duration: Option<Duration>) -> Result;
+    /// Queries the current state of the device.
+    fn state(&self) -> Result<DeviceState>;
+    /// Sets the device color and brightness simultaneously, turning it on.
+    ///
+    /// If neither is given, the device is simply turned on. If a duration is given, the change
+    /// is faded in over that time.
+    fn set(
+        &self,
+        color: Option<Color>,
+        brightness: Option<f32>,
+        duration: Option<Duration>,
+        fast: bool,
+    ) -> Result;
+    /// Sets the device brightness, leaving its color untouched.
+    fn brightness(&self, brightness: f32, duration: Option<Duration>, fast: bool) -> Result {
+        self.set(None, Some(brightness), duration, fast)
+    }
+    /// Sets the device color, leaving its brightness untouched.
+    fn color(&self, color: Color, duration: Option<Duration>, fast: bool) -> Result {
+        self.set(Some(color), None, duration, fast)
+    }
+    /// Changes the device's color and brightness relative to their current values.
+    ///
+    /// By default, this reads the device's state and sets the adjusted values (turning it on).
+    fn adjust(&self, delta: &Delta, duration: Option<Duration>, fast: bool) -> Result {
+        let (color, brightness) = delta.apply(&self.state()?);
+        self.set(color, brightness, duration, fast)
+    }
+    /// Runs an effect on the device.
+    fn effect(&self, effect: &Effect) -> Result {
+        Err(Error::UnsupportedEffect(effect.name()))
+    }
+    /// Whether the device fades between states itself.
+    ///
+    /// Fades are emulated (see [`emulate_power`] and [`emulate_set`]) for backends which don't.
+    fn transitions(&self) -> bool {
+        true
+    }
+}
+
+/// How often emulated fades update the device.
+const FADE_STEP: Duration = Duration::from_millis(100);
+/// The most updates an emulated fade sends.
+const MAX_FADE_STEPS: u32 = 100;
+
+/// Steps the device's brightness from one level to another over the given duration.
+fn fade(backend: &dyn Backend, from: f32, to: f32, duration: Duration, fast: bool) -> Result {
+    let steps =
+        (duration.as_millis() / FADE_STEP.as_millis()).clamp(1, MAX_FADE_STEPS.into()) as u32;
+    for step in 1..=steps {
+        thread::sleep(duration / steps);
+        let brightness = from + (to - from) * step as f32 / steps as f32;
+        backend.set(None, Some(brightness), None, fast)?;
+    }
+    Ok(())
+}
+
+/// Changes the power state of a device which can't fade by itself, fading its brightness.
+///
+/// Turning on fades up from darkness to the device's current brightness (or full brightness, if
+/// it was dimmed all the way down); turning off fades down to darkness before switching off.
+pub fn emulate_power(backend: &dyn Backend, on: bool, duration: Duration, fast: bool) -> Result {
+    let state = backend.state()?;
+    let brightness = state.brightness.filter(|b| *b > 0.0).unwrap_or(1.0);
+    if on {
+        if state.power == Some(true) {
+            return Ok(());
+        }
+        backend.set(None, Some(0.0), None, fast)?;
+        fade(backend, 0.0, brightness, duration, fast)
+    } else {
+        if state.power == Some(true) {
+            fade(backend, brightness, 0.0, duration, fast)?;
+        }
+        backend.power(false, None, fast)
+    }
+}
+
+/// Sets the color and brightness of a device which can't fade by itself, fading its brightness.
+///
+/// Colors can't be blended sensibly in general, so the color changes at the start of the fade.
+pub fn emulate_set(
+    backend: &dyn Backend,
+    color: Option<Color>,
+    brightness: Option<f32>,
+    duration: Duration,
+    fast: bool,
+) -> Result {
+    let state = backend.state()?;
+    let current = match state.power {
+        Some(true) => state.brightness.unwrap_or(0.0),
+        _ => 0.0,
+    };
+    let target = brightness.or(state.brightness).unwrap_or(1.0);
+    backend.set(color, Some(current), None, fast)?;
+    fade(backend, current, target, duration, fast)
+}
+
+/// A light, as described by the LIFX HTTP API's list endpoint.
+#[derive(Deserialize)]
+struct Light {
+    connected: bool,
+    power: String,
+    color: LightColor,
+    brightness: f32,
+    seconds_since_seen: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct LightColor {
+    hue: f32,
+    saturation: f32,
+    kelvin: u16,
+}
+
+impl From<Light> for DeviceState {
+    fn from(light: Light) -> Self {
+        let last_seen = light
+            .seconds_since_seen
+            .and_then(parse::seconds)
+            .and_then(|d| SystemTime::now().checked_sub(d));
+        Self {
+            power: Some(light.power == "on"),
+            hue: Some(light.color.hue),
+            saturation: Some(light.color.saturation),
+            brightness: Some(light.brightness),
+            kelvin: Some(light.color.kelvin),
+            reachable: light.connected,
+            last_seen,
+        }
+    }
+}
+
+/// A LIFX bulb managed through the LIFX HTTP API.
+pub struct LifxHttp<'a> {
+    client: &'a Client,
+    selector: &'a Selector,
+}
+
+impl<'a> LifxHttp<'a> {
+    /// Creates a backend addressing the bulbs matched by the selector.
+    pub fn new(client: &'a Client, selector: &'a Selector) -> Self {
+        Self { client, selector }
+    }
+}
+
+impl<'a> Backend for LifxHttp<'a> {
+    fn power(&self, on: bool, duration: Option<Duration>, fast: bool) -> Result {
+        self.client
+            .select(self.selector.clone())
+            .set_state()
+            .power(on)
+            .transition(duration.unwrap_or_default())
+            .fast(fast)
+            .send()?;
+        Ok(())
+    }
+    fn toggle(&self, duration: Option<Duration>) -> Result {
+        self.client
+            .select(self.selector.clone())
+            .toggle()
+            .transition(duration.unwrap_or_default())
+            .send()?;
+        Ok(())
+    }
+    fn state(&self) -> Result<DeviceState> {
+        let mut response = self.client.select(self.selector.clone()).list().send()?;
+        let lights: Vec<Light> = response
+            .json()
+            .map_err(|err| Error::Malformed(err.to_string()))?;
+        lights
+            .into_iter()
+            .next()
+            .map(DeviceState::from)
+            .ok_or(Error::NoResponse)
+    }
+    fn set(
+        &self,
+        color: Option<Color>,
+        brightness: Option<f32>,
+        duration: Option<Duration>,
+        fast: bool,
+    ) -> Result {
+        let (color, brightness) = match (color, brightness) {
+            (None, Some(b)) => (Some(Color::Brightness(b)), None),
+            p => p,
+        };
+        if let Some(c) = color {
+            let selected = self.client.select(self.selector.clone());
+            let duration = duration.unwrap_or_default();
+            if let Some(b) = brightness {
+                selected
+                    .set_state()
+                    .color(c)
+                    .brightness(b)
+                    .power(true)
+                    .transition(duration)
+                    .fast(fast)
+                    .send()?;
+            } else {
+                selected
+                    .set_state()
+                    .color(c)
+                    .power(true)
+                    .transition(duration)
+                    .fast(fast)
+                    .send()?;
+            }
+            Ok(())
+        } else {
+            self.power(true, duration, fast)
+        }
+    }
+    /// Adjusts the bulbs using the LIFX state delta endpoint, which doesn't change their power.
+    ///
+    /// The endpoint has no fast mode, so `fast` is ignored.
+    fn adjust(&self, delta: &Delta, duration: Option<Duration>, _fast: bool) -> Result {
+        let selected = self.client.select(self.selector.clone());
+        let mut builder = selected.change_state();
+        if let Some(brightness) = delta.brightness {
+            builder.brightness(brightness);
+        }
+        if let Some(hue) = delta.hue {
+            builder.hue(hue.round() as i16);
+        }
+        if let Some(saturation) = delta.saturation {
+            builder.saturation(saturation);
+        }
+        if let Some(kelvin) = delta.kelvin {
+            builder.kelvin(kelvin.clamp(i16::MIN.into(), i16::MAX.into()) as i16);
+        }
+        builder.transition(duration.unwrap_or_default()).send()?;
+        Ok(())
+    }
+    /// Runs a waveform effect; morph and flame aren't available through `lifxi`, and neither is
+    /// the peak of a pulse.
+    fn effect(&self, effect: &Effect) -> Result {
+        let selected = self.client.select(self.selector.clone());
+        let period = |w: &Waveform| w.period.unwrap_or(Duration::from_secs(1));
+        match effect {
+            Effect::Breathe(w) => {
+                let mut builder = selected.breathe(w.color.clone());
+                builder
+                    .period(period(w))
+                    .cycles(w.cycles.unwrap_or(1))
+                    .persist(w.persist)
+                    .peak(w.peak.unwrap_or(0.5));
+                if let Some(from) = &w.from {
+                    builder.from(from.clone());
+                }
+                builder.send()?;
+            }
+            Effect::Pulse(w) => {
+                if w.peak.is_some() {
+                    return Err(Error::UnsupportedEffect("pulse with a peak"));
+                }
+                let mut builder = selected.pulse(w.color.clone());
+                builder
+                    .period(period(w))
+                    .cycles(w.cycles.unwrap_or(1))
+                    .persist(w.persist);
+                if let Some(from) = &w.from {
+                    builder.from(from.clone());
+                }
+                builder.send()?;
+            }
+            _ => return Err(Error::UnsupportedEffect(effect.name())),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum Call {
+        Power(bool),
+        Toggle,
+        /// Whether a color was given, and the brightness.
+        Set(bool, Option<f32>),
+    }
+
+    /// A stand-in backend which records the calls made against it.
+    #[derive(Default)]
+    pub(crate) struct Fake {
+        pub calls: RefCell<Vec<Call>>,
+        pub state: DeviceState,
+        /// Whether the backend fades by itself.
+        pub fades: bool,
+    }
+
+    impl Backend for Fake {
+        fn power(&self, on: bool, _duration: Option<Duration>, _fast: bool) -> Result {
+            self.calls.borrow_mut().push(Call::Power(on));
+            Ok(())
+        }
+        fn toggle(&self, _duration: Option<Duration>) -> Result {
+            self.calls.borrow_mut().push(Call::Toggle);
+            Ok(())
+        }
+        fn state(&self) -> Result<DeviceState> {
+            Ok(self.state.clone())
+        }
+        fn set(
+            &self,
+            color: Option<Color>,
+            brightness: Option<f32>,
+            _duration: Option<Duration>,
+            _fast: bool,
+        ) -> Result {
+            self.calls
+                .borrow_mut()
+                .push(Call::Set(color.is_some(), brightness));
+            Ok(())
+        }
+        fn transitions(&self) -> bool {
+            self.fades
+        }
+    }
+
+    #[test]
+    fn provided() {
+        let fake = Fake::default();
+        let backend: &dyn Backend = &fake;
+        backend.brightness(0.5, None, true).unwrap();
+        backend.color(Color::Red, None, true).unwrap();
+        backend.toggle(None).unwrap();
+        assert_eq!(
+            *fake.calls.borrow(),
+            vec![
+                Call::Set(false, Some(0.5)),
+                Call::Set(true, None),
+                Call::Toggle
+            ]
+        );
+    }
+
+    #[test]
+    fn adjusted() {
+        let fake = Fake {
+            state: DeviceState {
+                power: Some(true),
+                brightness: Some(0.5),
+                ..DeviceState::default()
+            },
+            ..Fake::default()
+        };
+        let delta = Delta {
+            brightness: Some(-0.25),
+            ..Delta::default()
+        };
+        fake.adjust(&delta, None, true).unwrap();
+        assert_eq!(*fake.calls.borrow(), vec![Call::Set(false, Some(0.25))]);
+    }
+
+    #[test]
+    fn emulated() {
+        let duration = Duration::from_millis(200);
+        let fake = Fake {
+            state: DeviceState {
+                power: Some(true),
+                brightness: Some(0.5),
+                ..DeviceState::default()
+            },
+            ..Fake::default()
+        };
+        emulate_power(&fake, false, duration, true).unwrap();
+        assert_eq!(
+            fake.calls.replace(Vec::new()),
+            vec![
+                Call::Set(false, Some(0.25)),
+                Call::Set(false, Some(0.0)),
+                Call::Power(false)
+            ]
+        );
+        emulate_set(&fake, Some(Color::Red), Some(1.0), duration, true).unwrap();
+        assert_eq!(
+            fake.calls.replace(Vec::new()),
+            vec![
+                Call::Set(true, Some(0.5)),
+                Call::Set(false, Some(0.75)),
+                Call::Set(false, Some(1.0))
+            ]
+        );
+        let fake = Fake::default();
+        emulate_power(&fake, true, duration, true).unwrap();
+        assert_eq!(
+            *fake.calls.borrow(),
+            vec![
+                Call::Set(false, Some(0.0)),
+                Call::Set(false, Some(0.5)),
+                Call::Set(false, Some(1.0))
+            ]
+        );
+    }
+}
@@ -0,0 +1,373 @@
+//! Config file diagnostics.
+//!
+//! [`check`] looks for problems that would otherwise only surface once the config is used (or as
+//! a bare parse failure), pointing at where in the file each one is.
+
+use std::fmt;
+
+use lifxi::http::Selector;
+use toml::Spanned;
+
+use crate::{
+    config::{self, Config},
+    lan,
+};
+
+/// How serious a problem is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The config can't be used as written.
+    Error,
+    /// The config works, but probably not as intended.
+    Warning,
+}
+
+/// The kinds of problems [`check`] reports.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Code {
+    /// The file isn't valid TOML, or doesn't match the config format.
+    Parse,
+    /// A name or alias is used more than once.
+    DuplicateName,
+    /// A name or alias is a device index, so [`Config::find`] may return a different device.
+    ShadowedIndex,
+    /// The device type isn't one `adm` knows about.
+    UnknownType,
+    /// A LIFX selector couldn't be parsed.
+    InvalidSelector,
+    /// An IP or MAC address couldn't be parsed.
+    InvalidAddress,
+    /// A LAN device has neither an IP nor a MAC address.
+    MissingAddress,
+    /// LIFX devices are configured without a LIFX secret.
+    MissingSecret,
+}
+
+/// A single problem with a config file.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Diagnostic {
+    /// How serious the problem is.
+    pub severity: Severity,
+    /// What kind of problem it is.
+    pub code: Code,
+    /// A description of the problem.
+    pub message: String,
+    /// The line the problem is on (starting at 1), if known.
+    pub line: Option<usize>,
+    /// The column the problem starts at (starting at 1), if known.
+    pub column: Option<usize>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+/// The problems found in a config file, in the order they appear.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Report {
+    /// The problems found.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+    /// The number of problems which make the config unusable.
+    pub fn errors(&self) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count()
+    }
+    /// Whether the config is usable (warnings aside).
+    pub fn is_valid(&self) -> bool {
+        self.errors() == 0
+    }
+}
+
+/// The parts of the config which are checked, with their locations in the file.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RawConfig {
+    #[serde(default)]
+    devices: Vec<RawDevice>,
+    lifx_secret: Option<toml::Value>,
+    groups: Option<Vec<RawNamed>>,
+    scenes: Option<Vec<RawNamed>>,
+}
+
+#[derive(Deserialize)]
+struct RawDevice {
+    #[serde(rename = "type")]
+    kind: Option<Spanned<String>>,
+    name: Option<Spanned<String>>,
+    alternatives: Option<Vec<Spanned<String>>>,
+    selector: Option<Spanned<String>>,
+    address: Option<Spanned<String>>,
+    mac: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+struct RawNamed {
+    name: Option<Spanned<String>>,
+}
+
+/// Collects diagnostics, converting byte offsets into lines and columns.
+struct Checker<'a> {
+    source: &'a str,
+    report: Report,
+}
+
+impl<'a> Checker<'a> {
+    /// The (1-based) line and column of a byte offset into the source.
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let before = &self.source[..offset.min(self.source.len())];
+        let line = before.matches('\n').count() + 1;
+        let start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[start..].chars().count() + 1)
+    }
+    fn push(&mut self, severity: Severity, code: Code, message: String, offset: Option<usize>) {
+        let position = offset.map(|offset| self.position(offset));
+        self.report.diagnostics.push(Diagnostic {
+            severity,
+            code,
+            message,
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+        });
+    }
+    fn parse_error(&mut self, err: &toml::de::Error) {
+        let message = config::describe(err);
+        self.report.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            code: Code::Parse,
+            message,
+            line: err.line_col().map(|(line, _)| line + 1),
+            column: err.line_col().map(|(_, column)| column + 1),
+        });
+    }
+    /// Flags names used more than once (ignoring case).
+    fn duplicates(&mut self, kind: &str, names: &[&Spanned<String>]) {
+        for (index, name) in names.iter().enumerate() {
+            let first = names[..index]
+                .iter()
+                .find(|n| n.get_ref().eq_ignore_ascii_case(name.get_ref()));
+            if let Some(first) = first {
+                let (line, _) = self.position(first.start());
+                let message = format!(
+                    "The {} name {} is already used on line {}",
+                    kind,
+                    name.get_ref(),
+                    line
+                );
+                self.push(
+                    Severity::Error,
+                    Code::DuplicateName,
+                    message,
+                    Some(name.start()),
+                );
+            }
+        }
+    }
+    fn device(&mut self, device: &RawDevice) {
+        let name = device
+            .name
+            .as_ref()
+            .map_or("(unnamed)", |n| n.get_ref().as_str());
+        match device.kind.as_ref().map(|k| k.get_ref().as_str()) {
+            Some("lifx") => {
+                if let Some(selector) = &device.selector {
+                    if selector.get_ref().parse::<Selector>().is_err() {
+                        let message = format!(
+                            "Invalid selector {} for device {}",
+                            selector.get_ref(),
+                            name
+                        );
+                        self.push(
+                            Severity::Error,
+                            Code::InvalidSelector,
+                            message,
+                            Some(selector.start()),
+                        );
+                    }
+                }
+            }
+            Some("lifx-lan") => {
+                if device.address.is_none() && device.mac.is_none() {
+                    let message = format!("Device {} has neither an address nor a MAC", name);
+                    let offset = device.name.as_ref().map(Spanned::start);
+                    self.push(Severity::Error, Code::MissingAddress, message, offset);
+                }
+                let addresses = device
+                    .address
+                    .iter()
+                    .map(|a| (a, lan::parse_address(a.get_ref()).is_some()))
+                    .chain(
+                        device
+                            .mac
+                            .iter()
+                            .map(|m| (m, lan::parse_mac(m.get_ref()).is_some())),
+                    );
+                for (address, valid) in addresses {
+                    if !valid {
+                        let message =
+                            format!("Invalid address {} for device {}", address.get_ref(), name);
+                        self.push(
+                            Severity::Error,
+                            Code::InvalidAddress,
+                            message,
+                            Some(address.start()),
+                        );
+                    }
+                }
+            }
+            Some(kind) => {
+                let message = format!("Unknown type {} for device {}", kind, name);
+                let offset = device.kind.as_ref().map(Spanned::start);
+                self.push(Severity::Error, Code::UnknownType, message, offset);
+            }
+            // Reported when the config is parsed in full.
+            None => {}
+        }
+    }
+}
+
+/// Checks the contents of a config file for problems.
+pub fn check(source: &str) -> Report {
+    let mut checker = Checker {
+        source,
+        report: Report::default(),
+    };
+    let raw: RawConfig = match toml::from_str(source) {
+        Ok(raw) => raw,
+        Err(err) => {
+            checker.parse_error(&err);
+            return checker.report;
+        }
+    };
+    for device in &raw.devices {
+        checker.device(device);
+    }
+    let names: Vec<_> = raw
+        .devices
+        .iter()
+        .flat_map(|d| d.name.iter().chain(d.alternatives.iter().flatten()))
+        .collect();
+    checker.duplicates("device", &names);
+    for (index, device) in raw.devices.iter().enumerate() {
+        let names = device
+            .name
+            .iter()
+            .chain(device.alternatives.iter().flatten());
+        for name in names {
+            let shadowed = match name.get_ref().parse::<usize>() {
+                Ok(n) if n != index + 1 && n >= 1 && n <= raw.devices.len() => {
+                    raw.devices[n - 1].name.as_ref()
+                }
+                _ => None,
+            };
+            if let Some(other) = shadowed {
+                let message = format!(
+                    "The name {} is also the index of device {}, so it may refer to either",
+                    name.get_ref(),
+                    other.get_ref()
+                );
+                checker.push(
+                    Severity::Warning,
+                    Code::ShadowedIndex,
+                    message,
+                    Some(name.start()),
+                );
+            }
+        }
+    }
+    for (kind, entries) in &[("group", &raw.groups), ("scene", &raw.scenes)] {
+        let names: Vec<_> = entries.iter().flatten().flat_map(|e| &e.name).collect();
+        checker.duplicates(kind, &names);
+    }
+    let lifx = raw
+        .devices
+        .iter()
+        .find(|d| d.kind.as_ref().map(|k| k.get_ref().as_str()) == Some("lifx"));
+    if let (Some(device), None) = (lifx, &raw.lifx_secret) {
+        let message = "LIFX devices are configured, but no lifx-secret is set".to_string();
+        let offset = device.kind.as_ref().map(Spanned::start);
+        checker.push(Severity::Warning, Code::MissingSecret, message, offset);
+    }
+    // Anything the checks above didn't catch still has to deserialize.
+    if checker.report.is_valid() {
+        if let Err(err) = toml::from_str::<Config>(source) {
+            checker.parse_error(&err);
+        }
+    }
+    checker
+        .report
+        .diagnostics
+        .sort_by_key(|d| (d.line.is_none(), d.line, d.column));
+    checker.report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn codes(report: &Report) -> Vec<(Code, Option<usize>)> {
+        report
+            .diagnostics
+            .iter()
+            .map(|d| (d.code, d.line))
+            .collect()
+    }
+    #[test]
+    fn valid() {
+        let report = check("lifx-secret=\"secret\"\n[[devices]]\ntype=\"lifx\"\nname=\"porch\"\nselector=\"label:Porch\"\n");
+        assert!(report.diagnostics.is_empty());
+        assert!(report.is_valid());
+    }
+    #[test]
+    fn syntax() {
+        let report = check("[[devices]]\ntype=\"lifx\"\nname=porch\n");
+        assert_eq!(codes(&report), vec![(Code::Parse, Some(3))]);
+        assert!(!report.is_valid());
+        let report =
+            check("lifx-secret=\"secret\"\n[[devices]]\ntype=\"lifx\"\nselector=\"label:Porch\"\n");
+        assert_eq!(report.errors(), 1);
+        assert_eq!(report.diagnostics[0].code, Code::Parse);
+    }
+    #[test]
+    fn devices() {
+        let source = "[[devices]]\ntype=\"lifx\"\nname=\"porch\"\nalternatives=[\"2\"]\nselector=\"nonsense\"\n\n[[devices]]\ntype=\"lifx-lan\"\nname=\"Porch\"\n\n[[devices]]\ntype=\"lifx-lan\"\nname=\"desk\"\nalternatives=[\"3\"]\nmac=\"zz\"\n\n[[devices]]\ntype=\"hue\"\nname=\"lamp\"\n";
+        let report = check(source);
+        assert_eq!(
+            codes(&report),
+            vec![
+                (Code::MissingSecret, Some(2)),
+                (Code::ShadowedIndex, Some(4)),
+                (Code::InvalidSelector, Some(5)),
+                (Code::MissingAddress, Some(9)),
+                (Code::DuplicateName, Some(9)),
+                (Code::InvalidAddress, Some(15)),
+                (Code::UnknownType, Some(18)),
+            ]
+        );
+        assert_eq!(report.errors(), 5);
+        let duplicate = &report.diagnostics[4];
+        assert_eq!(duplicate.column, Some(6));
+        assert!(duplicate.message.contains("line 3"));
+        assert_eq!(
+            report.diagnostics[1].message,
+            "The name 2 is also the index of device Porch, so it may refer to either"
+        );
+    }
+    #[test]
+    fn names() {
+        let source = "devices=[]\n[[groups]]\nname=\"upstairs\"\ndevices=[]\n[[groups]]\nname=\"Upstairs\"\ndevices=[]\n[[scenes]]\nname=\"movie\"\ndevices=[]\n";
+        let report = check(source);
+        assert_eq!(codes(&report), vec![(Code::DuplicateName, Some(6))]);
+    }
+}
@@ -1,30 +1,48 @@
 //! Configuration file parsing.
 
 use std::{
-    fmt,
-    fs::{read_to_string, write},
-    io,
-    path::PathBuf,
+    env, fmt,
+    fs::{copy, create_dir_all, read_to_string, remove_file, rename, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
 };
 
-use crate::device::*;
+use crate::{
+    backend, context::Context, device::*, rule::Rule, scene::Scene, schedule::Schedule,
+    solar::Location,
+};
 
 use lazy_static::lazy_static;
-use lifxi::http::Client;
+use lifxi::http::{Client, Selector};
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table};
 
+// These globals are a convenience for the binaries, which only ever use the config file; library
+// users should build a [`Context`] from their own [`Config`] instead.
 lazy_static! {
+    /// The context built from the configuration file.
+    pub static ref CONTEXT: Context = Context::new(CONFIG.clone());
     /// The shared client to be used for all LIFX operations.
-    pub static ref LIFX_CLIENT: Client = Client::new(LIFX_SECRET.to_string());
-    /// The mqtt broker hostname.
-    pub static ref MQTT_HOST: String = CONFIG.mqtt_host.clone().expect("MQTT mode used but no host configured.");
+    pub static ref LIFX_CLIENT: &'static Client = CONTEXT.lifx().expect("LIFX devices used without configuring a LIFX secret.");
     /// The mqtt broker hostname.
-    pub static ref MQTT_PORT: u16 = CONFIG.mqtt_port.unwrap_or(1883);
-    /// The LIFX API token to be used.
-    static ref LIFX_SECRET: String = CONFIG.lifx_secret.clone().expect("LIFX devices used without configuring a LIFX secret.");
+    pub static ref MQTT_HOST: String = CONTEXT.mqtt_host().expect("MQTT mode used but no host configured.").to_string();
+    /// The mqtt broker port.
+    pub static ref MQTT_PORT: u16 = CONTEXT.mqtt_port();
     /// The parsed configuration file.
-    pub static ref CONFIG: Config = Config::parse().expect("Failed to parse config file.");
+    pub static ref CONFIG: Config = Config::parse().unwrap_or_else(|err| {
+        panic!("Failed to parse config file ({}); run `adm config check` for details.", err)
+    });
+    /// The config file location chosen with [`Config::set_path`].
+    static ref PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+    /// The profile chosen with [`Config::set_profile`].
+    static ref PROFILE: Mutex<Option<String>> = Mutex::new(None);
 }
 
+/// The environment variable naming the config file to use.
+pub const CONFIG_VAR: &str = "ADM_CONFIG";
+/// The environment variable naming the profile to use.
+pub const PROFILE_VAR: &str = "ADM_PROFILE";
+
 #[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
@@ -36,17 +54,58 @@ pub struct Config {
     pub mqtt_host: Option<String>,
     /// The user's configured MQTT broker port (1883 is used if not specified).
     pub mqtt_port: Option<u16>,
+    /// How the CLI should reach devices (MQTT is used if not specified).
+    pub mode: Option<Mode>,
+    /// How often, in seconds, the MQTT client should poll and publish device states (60 seconds
+    /// is used if not specified; 0 disables polling).
+    pub state_interval: Option<u64>,
+    /// The user's configured scenes.
+    pub scenes: Option<Vec<Scene>>,
+    /// The user's configured device groups (e.g. rooms).
+    pub groups: Option<Vec<Group>>,
+    /// The user's configured schedules, run by the MQTT client.
+    pub schedules: Option<Vec<Schedule>>,
+    /// The user's configured rules, evaluated by the MQTT client.
+    pub rules: Option<Vec<Rule>>,
+    /// The user's latitude, in degrees north (used for sunrise/sunset times).
+    pub latitude: Option<f64>,
+    /// The user's longitude, in degrees east (used for sunrise/sunset times).
+    pub longitude: Option<f64>,
+}
+
+/// A named set of devices, addressable anywhere a device name is accepted.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Group {
+    /// The group name (e.g. `downstairs`).
+    pub name: String,
+    /// The devices in the group, by name or alias.
+    pub devices: Vec<String>,
+}
+
+/// The ways in which commands can reach devices.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Commands are published to the MQTT broker, to be carried out by the MQTT client.
+    Mqtt,
+    /// Commands are carried out in-process.
+    Direct,
 }
 
 /// Represents an error encountered while reading and parsing a config file.
 ///
 /// These errors come in two flavors: I/O errors and parsing errors.
-#[derive(Debug)]
+///
+/// Since the config file contains the LIFX secret, errors concerning it never include its value.
 pub enum Error {
     /// An I/O error occurred.
     Io(io::Error),
     /// A TOML parsing error occured.
     Toml(toml::de::Error),
+    /// No location was given for the config file, and there's no home directory to look in.
+    NoPath,
+    /// The profile name isn't usable as a file name.
+    InvalidProfile(String),
 }
 
 impl From<io::Error> for Error {
@@ -61,38 +120,340 @@ impl From<toml::de::Error> for Error {
     }
 }
 
+/// Describes a TOML error without risking leaking the LIFX secret.
+pub(crate) fn describe(err: &toml::de::Error) -> String {
+    let message = err.to_string();
+    if !message.contains("lifx-secret") {
+        return message;
+    }
+    match err.line_col() {
+        Some((line, col)) => format!(
+            "invalid lifx-secret (value redacted) at line {} column {}",
+            line + 1,
+            col + 1
+        ),
+        None => "invalid lifx-secret (value redacted)".to_string(),
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            Io(err) => f.debug_tuple("Io").field(err).finish(),
+            Toml(err) => f.debug_tuple("Toml").field(&describe(err)).finish(),
+            NoPath => write!(f, "NoPath"),
+            InvalidProfile(name) => f.debug_tuple("InvalidProfile").field(name).finish(),
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::Error::*;
         match self {
             Io(err) => write!(f, "I/O error: {}", err),
-            Toml(err) => write!(f, "Parse error: {}", err),
+            Toml(err) => write!(f, "Parse error: {}", describe(err)),
+            NoPath => write!(
+                f,
+                "No home directory found; set {} or pass --config",
+                CONFIG_VAR
+            ),
+            InvalidProfile(name) => write!(f, "Invalid profile name {}", name),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+/// Rewrites an existing config file to hold the updated config, keeping the formatting and
+/// comments of everything that hasn't changed.
+///
+/// Falls back to the updated config as is if the existing file isn't valid TOML.
+fn update(existing: &str, updated: &str) -> String {
+    match (
+        existing.parse::<DocumentMut>(),
+        updated.parse::<DocumentMut>(),
+    ) {
+        (Ok(mut document), Ok(updated)) => {
+            merge_table(document.as_table_mut(), updated.as_table());
+            document.to_string()
+        }
+        _ => updated.to_string(),
+    }
+}
+
+/// Updates a table in place to match another, keeping any entries which are unchanged.
+fn merge_table(old: &mut Table, new: &Table) {
+    old.retain(|key, _| new.contains_key(key));
+    for (key, item) in new.iter() {
+        match old.get_mut(key) {
+            Some(existing) => merge_item(existing, item),
+            None => {
+                old.insert(key, detach(item));
+            }
+        }
+    }
+}
+
+fn merge_item(old: &mut Item, new: &Item) {
+    match (old, new) {
+        (Item::Table(old), Item::Table(new)) => merge_table(old, new),
+        (Item::ArrayOfTables(old), Item::ArrayOfTables(new)) => merge_array(old, new),
+        (Item::Value(old), Item::Value(new)) => {
+            // Values are compared by what they mean, so e.g. literal strings are left alone.
+            let parse = |value: &toml_edit::Value| format!("v = {}", value).parse::<toml::Value>();
+            let same = match (parse(old), parse(new)) {
+                (Ok(old), Ok(new)) => old == new,
+                _ => false,
+            };
+            if !same {
+                // The decor holds the surrounding whitespace and any trailing comment.
+                let decor = old.decor().clone();
+                *old = new.clone();
+                *old.decor_mut() = decor;
+            }
+        }
+        (old, new) => *old = detach(new),
+    }
+}
+
+/// Updates an array of tables (e.g. `[[devices]]`) to match another, pairing up entries by name
+/// (or by position, for unnamed entries) so that edited entries keep their comments.
+fn merge_array(old: &mut ArrayOfTables, new: &ArrayOfTables) {
+    let name = |table: &Table| table.get("name").and_then(Item::as_str).map(str::to_string);
+    let mut remaining: Vec<Option<Table>> = old.iter().cloned().map(Some).collect();
+    let mut merged = ArrayOfTables::new();
+    for (index, table) in new.iter().enumerate() {
+        let matched = match name(table) {
+            Some(n) => remaining
+                .iter()
+                .position(|t| t.as_ref().and_then(name).as_ref() == Some(&n)),
+            None => Some(index).filter(|&i| i < remaining.len()),
+        };
+        match matched.and_then(|i| remaining[i].take()) {
+            Some(mut existing) => {
+                merge_table(&mut existing, table);
+                merged.push(existing);
+            }
+            None => merged.push(detach_table(table)),
+        }
+    }
+    *old = merged;
+}
+
+/// Copies an item from another document.
+///
+/// Tables remember where they appeared in their document, which would misplace them in this one,
+/// so they're rebuilt without positions (and are placed after their predecessors).
+fn detach(item: &Item) -> Item {
+    match item {
+        Item::Table(table) => Item::Table(detach_table(table)),
+        Item::ArrayOfTables(tables) => {
+            let mut detached = ArrayOfTables::new();
+            for table in tables.iter() {
+                detached.push(detach_table(table));
+            }
+            Item::ArrayOfTables(detached)
+        }
+        item => item.clone(),
+    }
+}
+
+fn detach_table(table: &Table) -> Table {
+    let mut detached = Table::new();
+    for (key, item) in table.iter() {
+        detached.insert(key, detach(item));
+    }
+    detached
+}
+
+/// The path of a file next to the given one, with a suffix appended to its name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Restricts a file to its owner, since config files may hold the LIFX secret.
+#[cfg(unix)]
+fn restrict(path: &Path) -> io::Result<()> {
+    use std::{fs::set_permissions, os::unix::fs::PermissionsExt};
+    set_permissions(path, PermissionsExt::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn restrict(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Opens a file for writing which only its owner can read.
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options.open(path)?;
+    // The mode only applies to new files, so a leftover file is restricted as well.
+    restrict(path)?;
+    Ok(file)
+}
+
+/// Copies a file to a `.bak` file alongside it.
+fn backup(path: &Path) -> io::Result<()> {
+    let backup = sibling(path, ".bak");
+    copy(path, &backup)?;
+    restrict(&backup)
+}
+
+/// Replaces a file's contents atomically, by writing them to a temporary file and renaming it
+/// over the original.
+fn replace(path: &Path, contents: &str) -> io::Result<()> {
+    let temporary = sibling(path, ".tmp");
+    let result = create_private(&temporary)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| rename(&temporary, path));
+    if result.is_err() {
+        let _ = remove_file(&temporary);
+    }
+    result
+}
+
+/// Checks whether LIFX accepts the given API secret by listing the user's lights.
+pub fn verify_lifx_secret(secret: &str) -> backend::Result<bool> {
+    let response = Client::new(secret).select(Selector::All).list().send()?;
+    Ok(response.status().is_success())
+}
+
+/// The directory config files live in by default.
+fn directory() -> Result<PathBuf, Error> {
+    directory_in(dirs::home_dir(), dirs::config_dir())
+}
+
+/// The directory config files live in, given the home and platform config directories.
+///
+/// This is `~/.adm` if it exists (as it did before adm followed the XDG base directory spec), and
+/// `adm` in the platform's config directory (e.g. `~/.config/adm`) otherwise.
+fn directory_in(home: Option<PathBuf>, config: Option<PathBuf>) -> Result<PathBuf, Error> {
+    let legacy = home.map(|home| home.join(".adm"));
+    match (legacy, config) {
+        (Some(legacy), _) if legacy.is_dir() => Ok(legacy),
+        (_, Some(config)) => Ok(config.join("adm")),
+        (Some(legacy), None) => Ok(legacy),
+        (None, None) => Err(Error::NoPath),
+    }
+}
+
+/// The path of the named profile's config file in the given config directory.
+fn profile(directory: &Path, name: &str) -> Result<PathBuf, Error> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(Error::InvalidProfile(name.to_string()));
+    }
+    Ok(directory.join("profiles").join(format!("{}.toml", name)))
+}
+
+/// Picks the config file location from an explicit path, an explicit profile, and the values of
+/// `ADM_CONFIG` and `ADM_PROFILE`, in that order of precedence.
+///
+/// Empty environment values are ignored; if nothing is given, `config.toml` in the config
+/// directory is used. The config directory is only looked up if it's needed.
+fn locate<F: FnOnce() -> Result<PathBuf, Error>>(
+    directory: F,
+    path: Option<PathBuf>,
+    name: Option<String>,
+    env_path: Option<PathBuf>,
+    env_name: Option<String>,
+) -> Result<PathBuf, Error> {
+    if let Some(path) = path {
+        return Ok(path);
+    }
+    if let Some(name) = name {
+        return profile(&directory()?, &name);
+    }
+    if let Some(path) = env_path.filter(|p| !p.as_os_str().is_empty()) {
+        return Ok(path);
+    }
+    if let Some(name) = env_name.filter(|p| !p.is_empty()) {
+        return profile(&directory()?, &name);
+    }
+    Ok(directory()?.join("config.toml"))
+}
+
 impl Config {
-    fn path() -> PathBuf {
-        dirs::home_dir()
-            .expect("Failed to get home directory?")
-            .join(".adm/config.toml")
+    /// Overrides the location of the config file (e.g. from a `--config` flag).
+    ///
+    /// This takes precedence over everything else, but only takes effect if called before
+    /// [`CONFIG`] is first used.
+    pub fn set_path<P: Into<PathBuf>>(path: P) {
+        *PATH.lock().unwrap() = Some(path.into());
+    }
+    /// Selects a named profile (e.g. from a `--profile` flag), whose config file is
+    /// `profiles/<name>.toml` in the config directory.
+    ///
+    /// This only takes effect if called before [`CONFIG`] is first used.
+    pub fn set_profile<S: Into<String>>(name: S) {
+        *PROFILE.lock().unwrap() = Some(name.into());
     }
-    /// Loads the config file from ~/.adm/config.toml.
+    /// The location of the config file.
+    ///
+    /// In order of precedence, this is the path given to [`Config::set_path`], the profile given
+    /// to [`Config::set_profile`], the path in `ADM_CONFIG`, the profile in `ADM_PROFILE`, or
+    /// `config.toml` in the config directory (`~/.adm` if it exists, or the XDG config directory).
+    pub fn path() -> Result<PathBuf, Error> {
+        let path = PATH.lock().unwrap().clone();
+        let profile = PROFILE.lock().unwrap().clone();
+        locate(
+            directory,
+            path,
+            profile,
+            env::var_os(CONFIG_VAR).map(PathBuf::from),
+            env::var(PROFILE_VAR).ok(),
+        )
+    }
+    /// Loads the config file from [`Config::path`].
     pub fn parse() -> Result<Self, Error> {
-        let s = read_to_string(Self::path())?;
+        let s = read_to_string(Self::path()?)?;
         let config = toml::from_str(&s)?;
         Ok(config)
     }
-    /// Writes the updated config file to ~/.adm/config.toml.
-    pub fn write(&self) -> Result<(), io::Error> {
-        let s = toml::to_string_pretty(self).expect("Failed to serialize config as TOML.");
-        write(Self::path(), s)?;
+    /// Serializes the config as TOML.
+    fn to_toml(&self) -> String {
+        // Going through a `Value` emits plain values before tables, which TOML requires.
+        toml::Value::try_from(self)
+            .and_then(|value| toml::to_string_pretty(&value))
+            .expect("Failed to serialize config as TOML.")
+    }
+    /// Writes the updated config file to [`Config::path`], creating its directory if needed.
+    ///
+    /// Comments and formatting in the existing file are kept wherever the config hasn't changed,
+    /// and the previous file is kept alongside it with a `.bak` extension. The new file replaces
+    /// the old one only once it's written in full, and is readable only by its owner since it
+    /// holds the LIFX secret.
+    pub fn write(&self) -> Result<(), Error> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            create_dir_all(parent)?;
+        }
+        let contents = match read_to_string(&path) {
+            Ok(existing) => {
+                backup(&path)?;
+                update(&existing, &self.to_toml())
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => self.to_toml(),
+            Err(err) => return Err(err.into()),
+        };
+        replace(&path, &contents)?;
         Ok(())
     }
     /// Finds the specified device in the list of configured devices.
-    pub fn find<'a, S: ToString>(&'a self, s: S) -> Option<&'a Device> {
+    pub fn find<S: ToString>(&self, s: S) -> Option<&Device> {
         let s = s.to_string();
         self.devices
             .iter()
@@ -102,13 +463,61 @@ impl Config {
                     || device
                         .alternatives
                         .iter()
-                        .map(|d| d.iter())
                         .flatten()
                         .any(|alt| alt.eq_ignore_ascii_case(&s))
                     || format!("{}", (index + 1)) == s
             })
             .map(|(_, d)| d)
     }
+    /// Finds the named group in the list of configured groups.
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups
+            .iter()
+            .flatten()
+            .find(|group| group.name.eq_ignore_ascii_case(name))
+    }
+    /// Resolves a device specifier to the devices it refers to.
+    ///
+    /// Specifiers may name a single device (as accepted by [`find`](Config::find)), a group, or
+    /// every device (`*` or `all`), and several specifiers may be separated by commas. Devices take
+    /// precedence over groups of the same name. Each device is returned at most once.
+    pub fn resolve(&self, s: &str) -> Vec<&Device> {
+        let mut devices: Vec<&Device> = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            let matched = if let Some(device) = self.find(part) {
+                vec![device]
+            } else if let Some(group) = self.group(part) {
+                group.devices.iter().filter_map(|d| self.find(d)).collect()
+            } else if part == "*" || part.eq_ignore_ascii_case("all") {
+                self.devices.iter().collect()
+            } else {
+                Vec::new()
+            };
+            for device in matched {
+                if !devices.iter().any(|d| std::ptr::eq(*d, device)) {
+                    devices.push(device);
+                }
+            }
+        }
+        devices
+    }
+    /// The user's location, if both a latitude and longitude are configured.
+    pub fn location(&self) -> Option<Location> {
+        match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => Some(Location {
+                latitude,
+                longitude,
+            }),
+            _ => None,
+        }
+    }
+    /// Finds the named scene in the list of configured scenes.
+    pub fn scene(&self, name: &str) -> Option<&Scene> {
+        self.scenes
+            .iter()
+            .flatten()
+            .find(|scene| scene.name.eq_ignore_ascii_case(name))
+    }
 }
 
 #[cfg(test)]
@@ -131,4 +540,147 @@ mod tests {
         assert!(config.find("0").is_none());
         assert!(config.find("4").is_none());
     }
+    #[test]
+    fn resolve() {
+        let config = toml::from_str::<Config>("[[devices]]\ntype=\"lifx\"\nname=\"foo\"\nselector=\"label:foo\"\n[[devices]]\ntype=\"lifx\"\nname=\"bar\"\nselector=\"label:bar\"\n[[devices]]\ntype=\"lifx\"\nname=\"baz\"\nselector=\"label:baz\"\nalternatives=[\"qux\"]\n[[groups]]\nname=\"downstairs\"\ndevices=[\"foo\", \"qux\", \"nope\"]\n").expect("Failed to parse config.");
+        let names = |s| {
+            config
+                .resolve(s)
+                .iter()
+                .map(|d| d.name.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("bar"), vec!["bar"]);
+        assert_eq!(names("Downstairs"), vec!["foo", "baz"]);
+        assert_eq!(names("*"), vec!["foo", "bar", "baz"]);
+        assert_eq!(names("all"), names("*"));
+        assert_eq!(names("bar, downstairs, foo"), vec!["bar", "foo", "baz"]);
+        assert!(names("upstairs").is_empty());
+        assert!(names("").is_empty());
+    }
+    #[test]
+    fn serialize() {
+        let config = toml::from_str::<Config>("lifx-secret = \"x\"\n[[devices]]\ntype=\"lifx\"\nname=\"foo\"\nselector=\"label:foo\"\n").expect("Failed to parse config.");
+        let reparsed =
+            toml::from_str::<Config>(&config.to_toml()).expect("Failed to reparse config.");
+        assert_eq!(reparsed.devices, config.devices);
+        assert_eq!(reparsed.lifx_secret, config.lifx_secret);
+    }
+    #[test]
+    fn paths() {
+        let base = env::temp_dir().join(format!("adm-paths-{}", std::process::id()));
+        let (home, xdg) = (base.join("home"), base.join("config"));
+        create_dir_all(&home).expect("Failed to create directory.");
+        let directory = directory_in(Some(home.clone()), Some(xdg.clone())).unwrap();
+        assert_eq!(directory, xdg.join("adm"));
+        create_dir_all(home.join(".adm")).expect("Failed to create directory.");
+        let legacy = directory_in(Some(home.clone()), Some(xdg)).unwrap();
+        assert_eq!(legacy, home.join(".adm"));
+        assert!(directory_in(None, None).is_err());
+        std::fs::remove_dir_all(&base).expect("Failed to clean up.");
+
+        let cabin = directory.join("profiles").join("cabin.toml");
+        assert_eq!(profile(&directory, "cabin").unwrap(), cabin);
+        assert!(profile(&directory, "../etc/passwd").is_err());
+        assert!(profile(&directory, "").is_err());
+        let explicit = PathBuf::from("/etc/adm.toml");
+        let from_env = PathBuf::from("/tmp/adm.toml");
+        let locate_with = |path: Option<&PathBuf>, name: Option<&str>| {
+            locate(
+                || Ok(directory.clone()),
+                path.cloned(),
+                name.map(str::to_string),
+                Some(from_env.clone()),
+                Some("home".to_string()),
+            )
+            .unwrap()
+        };
+        assert_eq!(locate_with(Some(&explicit), Some("cabin")), explicit);
+        assert_eq!(locate_with(None, Some("cabin")), cabin);
+        assert_eq!(locate_with(None, None), from_env);
+        let locate_env = |env_path: &str, env_name: &str| {
+            let env_path = Some(PathBuf::from(env_path));
+            let env_name = Some(env_name.to_string());
+            locate(|| Ok(directory.clone()), None, None, env_path, env_name).unwrap()
+        };
+        assert_eq!(locate_env("", "cabin"), cabin);
+        assert_eq!(locate_env("", ""), directory.join("config.toml"));
+        // An explicit path doesn't need the config directory at all.
+        let missing = || Err(Error::NoPath);
+        assert_eq!(
+            locate(missing, Some(explicit.clone()), None, None, None).unwrap(),
+            explicit
+        );
+    }
+
+    #[test]
+    fn redact() {
+        let err = toml::from_str::<Config>("devices = []\nlifx-secret = 8675309\n")
+            .map_err(Error::from)
+            .err()
+            .expect("Parsed invalid config.");
+        assert!(!err.to_string().contains("8675309"));
+        assert!(!format!("{:?}", err).contains("8675309"));
+        let err = toml::from_str::<Config>("devices = []\nmqtt-port = \"x\"\n")
+            .map_err(Error::from)
+            .err()
+            .expect("Parsed invalid config.");
+        assert!(err.to_string().contains("mqtt-port"));
+    }
+
+    #[test]
+    fn preserve_formatting() {
+        let existing = "# My lights\nlifx-secret = \"secret\" # from cloud.lifx.com\n\n[[devices]]\n# The porch light\ntype = \"lifx\"\nname = \"porch\"\nselector = 'label:Porch'\n\n[[devices]]\ntype = \"lifx-lan\"\nname = \"desk\"\naddress = \"192.168.1.20\"\n";
+        let mut config = toml::from_str::<Config>(existing).expect("Failed to parse config.");
+        config.devices.retain(|d| d.name != "desk");
+        config.devices[0].alternatives = Some(vec!["front".to_string()]);
+        config.devices.push(Device {
+            r#type: Type::LifxLan {
+                address: None,
+                mac: Some("d0:73:d5:00:00:01".to_string()),
+            },
+            name: "lamp".to_string(),
+            alternatives: None,
+        });
+        config.mqtt_host = Some("broker".to_string());
+        let updated = update(existing, &config.to_toml());
+        for comment in &["# My lights", "# from cloud.lifx.com", "# The porch light"] {
+            assert!(
+                updated.contains(comment),
+                "Lost {} from {}",
+                comment,
+                updated
+            );
+        }
+        assert!(updated.contains("'label:Porch'"));
+        assert!(!updated.contains("desk"));
+        assert!(updated.find("lifx-secret") < updated.find("[[devices]]"));
+        let reparsed = toml::from_str::<Config>(&updated).expect("Failed to parse update.");
+        assert_eq!(reparsed.devices, config.devices);
+        assert_eq!(reparsed.mqtt_host, config.mqtt_host);
+        assert_eq!(update("not toml [", &config.to_toml()), config.to_toml());
+    }
+
+    #[test]
+    fn atomic_write() {
+        let directory = env::temp_dir().join(format!("adm-config-{}", std::process::id()));
+        create_dir_all(&directory).expect("Failed to create directory.");
+        let path = directory.join("config.toml");
+        std::fs::write(&path, "devices = []\n").expect("Failed to write config.");
+        backup(&path).expect("Failed to back up config.");
+        replace(&path, "devices = []\nmode = \"direct\"\n").expect("Failed to replace config.");
+        let read = |path: &Path| read_to_string(path).expect("Failed to read file.");
+        assert_eq!(read(&path), "devices = []\nmode = \"direct\"\n");
+        assert_eq!(read(&sibling(&path, ".bak")), "devices = []\n");
+        assert!(!sibling(&path, ".tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            for path in &[path.clone(), sibling(&path, ".bak")] {
+                let mode = std::fs::metadata(path).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+        }
+        std::fs::remove_dir_all(&directory).expect("Failed to clean up.");
+    }
 }
@@ -0,0 +1,76 @@
+//! The state shared by device operations.
+//!
+//! A [`Context`] owns a [`Config`] along with the clients built from it, so embedders can supply
+//! their own configuration instead of relying on the globals in [`config`](crate::config).
+
+use lifxi::http::Client;
+
+use crate::{backend, config::Config};
+
+/// The port used when no MQTT port is configured.
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// A configuration together with the backend clients it describes.
+pub struct Context {
+    config: Config,
+    lifx: Option<Client>,
+}
+
+impl Context {
+    /// Creates a context from a configuration, building any clients it calls for.
+    pub fn new(config: Config) -> Self {
+        let lifx = config.lifx_secret.as_ref().map(Client::new);
+        Context { config, lifx }
+    }
+    /// The configuration the context was built from.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    /// The client used for LIFX HTTP devices, if a LIFX secret is configured.
+    pub fn lifx(&self) -> backend::Result<&Client> {
+        self.lifx.as_ref().ok_or(backend::Error::NoLifxSecret)
+    }
+    /// The MQTT broker hostname, if one is configured.
+    pub fn mqtt_host(&self) -> Option<&str> {
+        self.config.mqtt_host.as_deref()
+    }
+    /// The MQTT broker port.
+    pub fn mqtt_port(&self) -> u16 {
+        self.config.mqtt_port.unwrap_or(DEFAULT_MQTT_PORT)
+    }
+}
+
+impl From<Config> for Context {
+    fn from(config: Config) -> Self {
+        Context::new(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Error;
+    #[test]
+    fn context() {
+        let config = toml::from_str::<Config>("mqtt-host=\"broker\"\n[[devices]]\ntype=\"lifx\"\nname=\"foo\"\nselector=\"label:foo\"\n").expect("Failed to parse config.");
+        let context = Context::new(config);
+        assert_eq!(context.mqtt_host(), Some("broker"));
+        assert_eq!(context.mqtt_port(), DEFAULT_MQTT_PORT);
+        let device = context
+            .config()
+            .find("foo")
+            .expect("Failed to find device.");
+        match device.state(&context) {
+            Err(Error::NoLifxSecret) => {}
+            Err(err) => panic!("Unexpected error {}", err),
+            Ok(_) => panic!("Queried a device without a LIFX secret."),
+        }
+        let config =
+            toml::from_str::<Config>("devices=[]\nlifx-secret=\"secret\"\nmqtt-port=1884\n")
+                .expect("Failed to parse config.");
+        let context = Context::from(config);
+        assert!(context.lifx().is_ok());
+        assert_eq!(context.mqtt_host(), None);
+        assert_eq!(context.mqtt_port(), 1884);
+    }
+}
@@ -1,8 +1,15 @@
 //! Device management.
+use std::time::{Duration, SystemTime};
+
 use lifxi::http::prelude::*;
+use schemars::JsonSchema;
 
-// TODO: Add result wrapper.
-type Result = lifxi::http::ClientResult;
+use crate::{
+    backend::{self, Backend, LifxHttp, Result},
+    context::Context,
+    effect::Effect,
+    lan::LifxLan,
+};
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(tag = "type")]
@@ -12,6 +19,15 @@ pub enum Type {
     /// LIFX devices are managed using [`lifxi`](https://github.com/Aehmlo/lifxi).
     #[serde(rename = "lifx")]
     LifxBulb { selector: Selector },
+    /// A LIFX light bulb controlled over the local network.
+    ///
+    /// At least one of the IP address (optionally with a port) and MAC address must be given; if
+    /// only the MAC address is known, the bulb is found by broadcasting a discovery message.
+    #[serde(rename = "lifx-lan")]
+    LifxLan {
+        address: Option<String>,
+        mac: Option<String>,
+    },
 }
 
 /// The bread and butter of the device manager.
@@ -26,58 +42,217 @@ pub struct Device {
     pub alternatives: Option<Vec<String>>,
 }
 
+/// A snapshot of a device's state, independent of its backend.
+///
+/// Hues are in degrees; saturation and brightness are in `[0, 1]`. Components a backend can't
+/// report are left as `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceState {
+    /// Whether the device is on.
+    pub power: Option<bool>,
+    /// The hue of the device's color.
+    pub hue: Option<f32>,
+    /// The saturation of the device's color.
+    pub saturation: Option<f32>,
+    /// The brightness of the device.
+    pub brightness: Option<f32>,
+    /// The color temperature of the device, in Kelvin.
+    pub kelvin: Option<u16>,
+    /// Whether the device could be reached.
+    pub reachable: bool,
+    /// When the device was last seen, if known.
+    pub last_seen: Option<SystemTime>,
+}
+
+/// The coolest color temperature lights are adjusted to, in Kelvin.
+const MIN_KELVIN: i32 = 1500;
+/// The warmest color temperature lights are adjusted to, in Kelvin.
+const MAX_KELVIN: i32 = 9000;
+
+/// A relative change to a device's state, such as "10% brighter" or "500K warmer".
+///
+/// Hues are in degrees and wrap around; brightness and saturation are fractions of the full range
+/// and are clamped to `[0, 1]`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+pub struct Delta {
+    /// The change in brightness.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<f32>,
+    /// The change in hue.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hue: Option<f32>,
+    /// The change in saturation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saturation: Option<f32>,
+    /// The change in color temperature, in Kelvin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kelvin: Option<i32>,
+}
+
+impl Delta {
+    /// Whether the delta doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.brightness.is_none()
+            && self.hue.is_none()
+            && self.saturation.is_none()
+            && self.kelvin.is_none()
+    }
+    /// The color and brightness reached by applying the delta to the given state.
+    ///
+    /// Only the components the delta changes are returned; unknown components are assumed to be
+    /// zero (or, for color temperature, a neutral 3500K).
+    pub fn apply(&self, state: &DeviceState) -> (Option<Color>, Option<f32>) {
+        let mut components = Vec::new();
+        if let Some(hue) = self.hue {
+            let hue = (state.hue.unwrap_or(0.0) + hue).rem_euclid(360.0);
+            components.push(format!("hue:{}", hue));
+        }
+        if let Some(saturation) = self.saturation {
+            let saturation = (state.saturation.unwrap_or(0.0) + saturation).clamp(0.0, 1.0);
+            components.push(format!("saturation:{}", saturation));
+        }
+        if let Some(kelvin) = self.kelvin {
+            let current = state.kelvin.map_or(3500, i32::from);
+            let kelvin = (current + kelvin).clamp(MIN_KELVIN, MAX_KELVIN);
+            components.push(format!("kelvin:{}", kelvin));
+        }
+        let color = if components.is_empty() {
+            None
+        } else {
+            Some(Color::Custom(components.join(" ")))
+        };
+        let brightness = self
+            .brightness
+            .map(|b| (state.brightness.unwrap_or(0.0) + b).clamp(0.0, 1.0));
+        (color, brightness)
+    }
+}
+
+impl Type {
+    /// The name of the type, as used in the config file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::LifxBulb { .. } => "lifx",
+            Type::LifxLan { .. } => "lifx-lan",
+        }
+    }
+    /// Creates the backend responsible for devices of this type, using the context's clients.
+    pub fn backend<'a>(&'a self, context: &'a Context) -> Result<Box<dyn Backend + 'a>> {
+        Ok(match self {
+            Type::LifxBulb { selector } => Box::new(LifxHttp::new(context.lifx()?, selector)),
+            Type::LifxLan { address, mac } => Box::new(LifxLan::new(
+                address.as_ref().map(String::as_str),
+                mac.as_ref().map(String::as_str),
+            )),
+        })
+    }
+}
+
 impl Device {
-    /// Changes the power state of the device.
-    pub fn power(&self, on: bool, fast: bool) -> Result {
-        match &self.r#type {
-            Type::LifxBulb { selector } => crate::config::LIFX_CLIENT
-                .select(selector.clone())
-                .set_state()
-                .power(on)
-                .fast(fast)
-                .send(),
+    /// Queries the current state of the device.
+    pub fn state(&self, context: &Context) -> Result<DeviceState> {
+        self.r#type.backend(context)?.state()
+    }
+    /// Changes the power state of the device, fading over the given duration.
+    pub fn power(
+        &self,
+        context: &Context,
+        on: bool,
+        duration: Option<Duration>,
+        fast: bool,
+    ) -> Result {
+        Self::power_with(&*self.r#type.backend(context)?, on, duration, fast)
+    }
+    /// Toggles the device, fading over the given duration.
+    pub fn toggle(&self, context: &Context, duration: Option<Duration>) -> Result {
+        Self::toggle_with(&*self.r#type.backend(context)?, duration)
+    }
+    /// Sets the device color and brightness simultaneously, fading over the given duration.
+    pub fn set(
+        &self,
+        context: &Context,
+        color: Option<Color>,
+        brightness: Option<f32>,
+        duration: Option<Duration>,
+        fast: bool,
+    ) -> Result {
+        Self::set_with(
+            &*self.r#type.backend(context)?,
+            color,
+            brightness,
+            duration,
+            fast,
+        )
+    }
+    /// Changes the device's color and brightness relative to their current values, fading over the
+    /// given duration.
+    pub fn adjust(
+        &self,
+        context: &Context,
+        delta: &Delta,
+        duration: Option<Duration>,
+        fast: bool,
+    ) -> Result {
+        Self::adjust_with(&*self.r#type.backend(context)?, delta, duration, fast)
+    }
+    /// Runs an effect on the device.
+    pub fn effect(&self, context: &Context, effect: &Effect) -> Result {
+        self.r#type.backend(context)?.effect(effect)
+    }
+    /// Changes the power state of a device through the given backend, emulating the fade if the
+    /// backend can't fade by itself.
+    pub fn power_with(
+        backend: &dyn Backend,
+        on: bool,
+        duration: Option<Duration>,
+        fast: bool,
+    ) -> Result {
+        match duration {
+            Some(d) if !backend.transitions() => backend::emulate_power(backend, on, d, fast),
+            _ => backend.power(on, duration, fast),
         }
     }
-    /// Toggles the device.
-    pub fn toggle(&self) -> Result {
-        match &self.r#type {
-            Type::LifxBulb { selector } => crate::config::LIFX_CLIENT
-                .select(selector.clone())
-                .toggle()
-                .send(),
+    /// Toggles a device through the given backend, emulating the fade if the backend can't fade by
+    /// itself.
+    pub fn toggle_with(backend: &dyn Backend, duration: Option<Duration>) -> Result {
+        match duration {
+            Some(d) if !backend.transitions() => {
+                let on = backend.state()?.power == Some(true);
+                backend::emulate_power(backend, !on, d, false)
+            }
+            _ => backend.toggle(duration),
         }
     }
-    /// Sets the device color and brightness simultaneously.
-    pub fn set(&self, color: Option<Color>, brightness: Option<f32>, fast: bool) -> Result {
-        let (color, brightness) = match (color, brightness) {
-            (None, Some(b)) => (Some(Color::Brightness(b)), None),
-            p => p,
-        };
-        match &self.r#type {
-            Type::LifxBulb { selector } => {
-                if let Some(c) = color {
-                    if let Some(b) = brightness {
-                        crate::config::LIFX_CLIENT
-                            .select(selector.clone())
-                            .set_state()
-                            .color(c)
-                            .brightness(b)
-                            .power(true)
-                            .fast(fast)
-                            .send()
-                    } else {
-                        crate::config::LIFX_CLIENT
-                            .select(selector.clone())
-                            .set_state()
-                            .color(c)
-                            .power(true)
-                            .fast(fast)
-                            .send()
-                    }
-                } else {
-                    self.power(true, fast)
-                }
+    /// Sets a device's color and brightness through the given backend, emulating the fade if the
+    /// backend can't fade by itself.
+    pub fn set_with(
+        backend: &dyn Backend,
+        color: Option<Color>,
+        brightness: Option<f32>,
+        duration: Option<Duration>,
+        fast: bool,
+    ) -> Result {
+        match duration {
+            Some(d) if !backend.transitions() => {
+                backend::emulate_set(backend, color, brightness, d, fast)
             }
+            _ => backend.set(color, brightness, duration, fast),
+        }
+    }
+    /// Adjusts a device's color and brightness through the given backend, emulating the fade if
+    /// the backend can't fade by itself.
+    pub fn adjust_with(
+        backend: &dyn Backend,
+        delta: &Delta,
+        duration: Option<Duration>,
+        fast: bool,
+    ) -> Result {
+        match duration {
+            Some(d) if !backend.transitions() => {
+                let (color, brightness) = delta.apply(&backend.state()?);
+                backend::emulate_set(backend, color, brightness, d, fast)
+            }
+            _ => backend.adjust(delta, duration, fast),
         }
     }
 }
@@ -85,6 +260,8 @@ impl Device {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::tests::{Call, Fake};
+
     #[test]
     fn deserialize() {
         let device: Device =
@@ -95,7 +272,110 @@ mod tests {
                 selector: Selector::Label("Foo".to_owned())
             }
         );
+        let device: Device =
+            toml::from_str("type = \"lifx-lan\"\nmac = \"d0:73:d5:01:02:03\"\nname = \"porch\"")
+                .unwrap();
+        assert_eq!(
+            device.r#type,
+            Type::LifxLan {
+                address: None,
+                mac: Some("d0:73:d5:01:02:03".to_owned())
+            }
+        );
         assert!(toml::from_str::<Device>("").is_err());
         assert!(toml::from_str::<Device>("type = \"lifx\"").is_err());
     }
+
+    #[test]
+    fn deltas() {
+        let state = DeviceState {
+            hue: Some(350.0),
+            brightness: Some(0.95),
+            kelvin: Some(2700),
+            ..DeviceState::default()
+        };
+        let delta = Delta {
+            brightness: Some(0.1),
+            hue: Some(30.0),
+            kelvin: Some(-2000),
+            ..Delta::default()
+        };
+        assert_eq!(
+            delta.apply(&state),
+            (
+                Some(Color::Custom("hue:20 kelvin:1500".to_string())),
+                Some(1.0)
+            )
+        );
+        let dim = Delta {
+            brightness: Some(-0.5),
+            ..Delta::default()
+        };
+        assert_eq!(dim.apply(&state), (None, Some(0.45)));
+        assert!(Delta::default().is_empty());
+        assert!(!dim.is_empty());
+    }
+
+    #[test]
+    fn dispatch() {
+        let duration = Some(Duration::from_millis(100));
+        let fades = Fake {
+            fades: true,
+            ..Fake::default()
+        };
+        Device::power_with(&fades, true, duration, true).unwrap();
+        Device::toggle_with(&fades, duration).unwrap();
+        Device::set_with(&fades, Some(Color::Red), Some(0.5), duration, true).unwrap();
+        assert_eq!(
+            *fades.calls.borrow(),
+            vec![Call::Power(true), Call::Toggle, Call::Set(true, Some(0.5))]
+        );
+
+        // Without a duration, nothing needs emulating.
+        let fake = Fake::default();
+        Device::power_with(&fake, false, None, true).unwrap();
+        Device::toggle_with(&fake, None).unwrap();
+        assert_eq!(*fake.calls.borrow(), vec![Call::Power(false), Call::Toggle]);
+    }
+
+    #[test]
+    fn emulated() {
+        let duration = Some(Duration::from_millis(100));
+        let fake = Fake {
+            state: DeviceState {
+                power: Some(true),
+                brightness: Some(0.5),
+                ..DeviceState::default()
+            },
+            ..Fake::default()
+        };
+        Device::power_with(&fake, false, duration, true).unwrap();
+        assert_eq!(
+            fake.calls.replace(Vec::new()),
+            vec![Call::Set(false, Some(0.0)), Call::Power(false)]
+        );
+        // The bulb is on, so toggling fades it off.
+        Device::toggle_with(&fake, duration).unwrap();
+        assert_eq!(
+            fake.calls.replace(Vec::new()),
+            vec![Call::Set(false, Some(0.0)), Call::Power(false)]
+        );
+        Device::set_with(&fake, Some(Color::Red), Some(1.0), duration, true).unwrap();
+        assert_eq!(
+            fake.calls.replace(Vec::new()),
+            vec![Call::Set(true, Some(0.5)), Call::Set(false, Some(1.0))]
+        );
+        let delta = Delta {
+            brightness: Some(-0.25),
+            ..Delta::default()
+        };
+        Device::adjust_with(&fake, &delta, duration, true).unwrap();
+        assert_eq!(
+            fake.calls.replace(Vec::new()),
+            vec![Call::Set(false, Some(0.5)), Call::Set(false, Some(0.25))]
+        );
+        // Without a fade, the backend's own adjustment is used.
+        Device::adjust_with(&fake, &delta, None, true).unwrap();
+        assert_eq!(*fake.calls.borrow(), vec![Call::Set(false, Some(0.25))]);
+    }
 }
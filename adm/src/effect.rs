@@ -0,0 +1,161 @@
+//! Light effects.
+
+use std::time::Duration;
+
+use lifxi::http::Color;
+use schemars::JsonSchema;
+
+/// The parameters of a waveform effect, which cycles a light between two colors.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
+pub struct Waveform {
+    /// The color to cycle to.
+    #[schemars(with = "String")]
+    pub color: Color,
+    /// The color to cycle from (the light's current color if omitted).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub from: Option<Color>,
+    /// How long each cycle takes (one second if omitted).
+    #[serde(
+        default,
+        with = "crate::message::seconds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<f64>", range(min = 0))]
+    pub period: Option<Duration>,
+    /// How many cycles to run (one if omitted).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cycles: Option<u16>,
+    /// Whether the light should stay at `color` once the effect finishes.
+    #[serde(default)]
+    pub persist: bool,
+    /// Where in each cycle the light is closest to `color`, in `[0, 1]` (halfway if omitted).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0, max = 1))]
+    pub peak: Option<f32>,
+}
+
+impl Waveform {
+    /// A single one-second cycle to the given color.
+    pub fn new(color: Color) -> Self {
+        Self {
+            color,
+            from: None,
+            period: None,
+            cycles: None,
+            persist: false,
+            peak: None,
+        }
+    }
+}
+
+/// An effect a light can run.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "effect", rename_all = "lowercase")]
+pub enum Effect {
+    /// Smoothly fades between two colors.
+    Breathe(Waveform),
+    /// Switches abruptly between two colors.
+    Pulse(Waveform),
+    /// Drifts smoothly through a palette of colors (tiles only).
+    Morph {
+        /// The colors to drift through (at most 16).
+        #[schemars(with = "Vec<String>", length(max = 16))]
+        palette: Vec<Color>,
+        /// How long each movement takes.
+        #[serde(
+            default,
+            with = "crate::message::seconds",
+            skip_serializing_if = "Option::is_none"
+        )]
+        #[schemars(with = "Option<f64>", range(min = 0))]
+        period: Option<Duration>,
+        /// How long to run the effect for (forever if omitted).
+        #[serde(
+            default,
+            with = "crate::message::seconds",
+            skip_serializing_if = "Option::is_none"
+        )]
+        #[schemars(with = "Option<f64>", range(min = 0))]
+        duration: Option<Duration>,
+    },
+    /// Flickers like a flame (tiles only).
+    Flame {
+        /// How long each flicker takes.
+        #[serde(
+            default,
+            with = "crate::message::seconds",
+            skip_serializing_if = "Option::is_none"
+        )]
+        #[schemars(with = "Option<f64>", range(min = 0))]
+        period: Option<Duration>,
+        /// How long to run the effect for (forever if omitted).
+        #[serde(
+            default,
+            with = "crate::message::seconds",
+            skip_serializing_if = "Option::is_none"
+        )]
+        #[schemars(with = "Option<f64>", range(min = 0))]
+        duration: Option<Duration>,
+    },
+}
+
+impl Effect {
+    /// The name of the effect.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Breathe(_) => "breathe",
+            Effect::Pulse(_) => "pulse",
+            Effect::Morph { .. } => "morph",
+            Effect::Flame { .. } => "flame",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn serde() {
+        let effect = Effect::Breathe(Waveform {
+            period: Some(Duration::from_millis(1500)),
+            cycles: Some(3),
+            ..Waveform::new(Color::Red)
+        });
+        let s = toml::to_string(&effect).unwrap();
+        assert!(s.contains("effect = \"breathe\""));
+        assert!(s.contains("period = 1.5"));
+        assert_eq!(toml::from_str::<Effect>(&s).unwrap(), effect);
+        let flame = toml::from_str::<Effect>("effect = \"flame\"\nduration = 60").unwrap();
+        assert_eq!(
+            flame,
+            Effect::Flame {
+                period: None,
+                duration: Some(Duration::from_secs(60)),
+            }
+        );
+        assert_eq!(flame.name(), "flame");
+        assert!(toml::from_str::<Effect>("effect = \"strobe\"").is_err());
+    }
+
+    #[test]
+    fn payloads() {
+        use crate::message::MqttPayload;
+        let roundtrip =
+            |payload: &MqttPayload| MqttPayload::decode(&payload.encode().unwrap()).unwrap();
+        let pulse = Effect::Pulse(Waveform {
+            cycles: Some(3),
+            ..Waveform::new(Color::Red)
+        });
+        assert_eq!(
+            roundtrip(&MqttPayload::Effect(pulse.clone())),
+            MqttPayload::Effect(pulse)
+        );
+        let state = MqttPayload::State {
+            color: Some(Color::Red),
+            brightness: None,
+            duration: None,
+        };
+        assert_eq!(roundtrip(&state), state);
+    }
+}
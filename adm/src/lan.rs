@@ -0,0 +1,802 @@
+//! The LIFX LAN protocol.
+//!
+//! LIFX bulbs listen for a binary protocol on UDP port 56700, which lets them be controlled
+//! without a round trip through the LIFX cloud (or an internet connection at all). See
+//! [the protocol documentation](https://lan.developer.lifx.com) for details.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use lifxi::http::Color;
+
+use crate::{
+    backend::{Backend, Error, Result},
+    device::DeviceState,
+    effect::{Effect, Waveform},
+    parse::{self, Adjustment},
+};
+
+/// The port LIFX devices listen on.
+pub const PORT: u16 = 56700;
+
+const HEADER_SIZE: usize = 36;
+const PROTOCOL: u16 = 1024;
+const ATTEMPTS: usize = 3;
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The message types this module knows how to speak.
+pub mod kind {
+    /// Asks devices to report the services they offer (used for discovery).
+    pub const GET_SERVICE: u16 = 2;
+    /// A device's response to `GET_SERVICE`.
+    pub const STATE_SERVICE: u16 = 3;
+    /// Acknowledges a message sent with `ack_required` set.
+    pub const ACKNOWLEDGEMENT: u16 = 45;
+    /// Asks a light for its current state.
+    pub const GET: u16 = 101;
+    /// Sets the color of a light.
+    pub const SET_COLOR: u16 = 102;
+    /// Runs a waveform effect on a light.
+    pub const SET_WAVEFORM: u16 = 103;
+    /// A light's response to `GET`.
+    pub const STATE: u16 = 107;
+    /// Sets the power level of a light.
+    pub const SET_LIGHT_POWER: u16 = 117;
+    /// Runs a firmware effect on a tile.
+    pub const SET_TILE_EFFECT: u16 = 719;
+}
+
+/// The waveforms used by `SET_WAVEFORM`.
+mod waveform {
+    pub const SINE: u8 = 1;
+    pub const PULSE: u8 = 4;
+}
+
+/// The effects used by `SET_TILE_EFFECT`.
+mod tile_effect {
+    pub const MORPH: u8 = 2;
+    pub const FLAME: u8 = 3;
+}
+
+/// The most colors a tile effect's palette can hold.
+const MAX_PALETTE: usize = 16;
+/// How long each movement of a tile effect takes if unspecified.
+const DEFAULT_EFFECT_PERIOD: Duration = Duration::from_secs(3);
+
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// A LIFX LAN protocol packet.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Packet {
+    /// Whether the packet is addressed to all devices.
+    pub tagged: bool,
+    /// An identifier for the sender, echoed back in responses.
+    pub source: u32,
+    /// The MAC address of the target device, padded with two zero bytes (or all zero).
+    pub target: [u8; 8],
+    /// Whether the device should acknowledge the packet.
+    pub ack_required: bool,
+    /// Whether the device should respond with its state.
+    pub res_required: bool,
+    /// A sequence number, echoed back in responses.
+    pub sequence: u8,
+    /// The message type (see [`kind`]).
+    pub kind: u16,
+    /// The message payload.
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    /// Creates a packet of the given type, addressed to the given target.
+    pub fn new(kind: u16, target: [u8; 8], payload: Vec<u8>) -> Self {
+        Self {
+            tagged: target == [0; 8],
+            source: source(),
+            target,
+            ack_required: false,
+            res_required: false,
+            sequence: SEQUENCE.fetch_add(1, Ordering::Relaxed) as u8,
+            kind,
+            payload,
+        }
+    }
+    /// Creates a response to this packet, as a device would.
+    pub fn reply(&self, kind: u16, target: [u8; 8], payload: Vec<u8>) -> Self {
+        Self {
+            tagged: false,
+            source: self.source,
+            target,
+            ack_required: false,
+            res_required: false,
+            sequence: self.sequence,
+            kind,
+            payload,
+        }
+    }
+    /// Encodes the packet in its wire format.
+    pub fn encode(&self) -> Vec<u8> {
+        let size = (HEADER_SIZE + self.payload.len()) as u16;
+        let mut flags = PROTOCOL | 1 << 12;
+        if self.tagged {
+            flags |= 1 << 13;
+        }
+        let mut bytes = Vec::with_capacity(size as usize);
+        bytes.extend_from_slice(&size.to_le_bytes());
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&self.source.to_le_bytes());
+        bytes.extend_from_slice(&self.target);
+        bytes.extend_from_slice(&[0; 6]);
+        bytes.push(self.res_required as u8 | (self.ack_required as u8) << 1);
+        bytes.push(self.sequence);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&self.kind.to_le_bytes());
+        bytes.extend_from_slice(&[0; 2]);
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+    /// Decodes a packet from its wire format, if well-formed.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE || usize::from(u16_at(bytes, 0)) != bytes.len() {
+            return None;
+        }
+        let flags = u16_at(bytes, 2);
+        if flags & 0xfff != PROTOCOL {
+            return None;
+        }
+        let mut target = [0; 8];
+        target.copy_from_slice(&bytes[8..16]);
+        Some(Self {
+            tagged: flags & 1 << 13 != 0,
+            source: u32_at(bytes, 4),
+            target,
+            ack_required: bytes[22] & 0b10 != 0,
+            res_required: bytes[22] & 0b01 != 0,
+            sequence: bytes[23],
+            kind: u16_at(bytes, 32),
+            payload: bytes[HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Identifies this process to devices; sources of 0 and 1 have special meanings.
+fn source() -> u32 {
+    std::process::id().max(2)
+}
+
+/// A color in the representation used by the LAN protocol.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Hsbk {
+    /// The hue, scaled so that `0..=65535` covers `0..360` degrees.
+    pub hue: u16,
+    /// The saturation, scaled to `0..=65535`.
+    pub saturation: u16,
+    /// The brightness, scaled to `0..=65535`.
+    pub brightness: u16,
+    /// The color temperature, in Kelvin.
+    pub kelvin: u16,
+}
+
+fn scale(value: f32) -> u16 {
+    (value.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+impl Hsbk {
+    /// Applies a color adjustment.
+    pub fn adjust(&mut self, adjustment: Adjustment) {
+        match adjustment {
+            Adjustment::Hue(hue) => self.hue = scale(hue.rem_euclid(360.0) / 360.0),
+            Adjustment::Saturation(s) => self.saturation = scale(s),
+            Adjustment::Brightness(b) => self.brightness = scale(b),
+            Adjustment::Kelvin(k) => {
+                self.kelvin = k;
+                self.saturation = 0;
+            }
+        }
+    }
+    /// Applies a LIFX color, leaving any components it doesn't specify untouched.
+    pub fn apply(&mut self, color: &Color) -> Result {
+        use lifxi::http::Color::*;
+        let named = |hue| vec![Adjustment::Hue(hue), Adjustment::Saturation(1.0)];
+        let adjustments = match color {
+            Red => named(0.0),
+            Orange => named(36.0),
+            Yellow => named(60.0),
+            Green => named(120.0),
+            Blue => named(250.0),
+            Purple => named(280.0),
+            Pink => named(325.0),
+            White => vec![Adjustment::Saturation(0.0)],
+            Hue(hue) => vec![Adjustment::Hue(f32::from(*hue))],
+            Saturation(s) => vec![Adjustment::Saturation(*s)],
+            Brightness(b) => vec![Adjustment::Brightness(*b)],
+            Kelvin(k) => vec![Adjustment::Kelvin(*k)],
+            Hsbk(hue, saturation, brightness, kelvin) => {
+                // Setting the temperature clears the saturation, so it goes first.
+                let mut adjustments = Vec::new();
+                adjustments.extend(kelvin.map(Adjustment::Kelvin));
+                adjustments.extend(hue.map(|h| Adjustment::Hue(f32::from(h))));
+                adjustments.extend(saturation.map(Adjustment::Saturation));
+                adjustments.extend(brightness.map(Adjustment::Brightness));
+                adjustments
+            }
+            Rgb([r, g, b]) => parse::rgb(*r, *g, *b),
+            RgbStr(s) => parse::color(&format!("#{}", s.trim_start_matches('#')))
+                .ok_or(Error::UnsupportedColor)?,
+            Custom(s) => parse::color(s).ok_or(Error::UnsupportedColor)?,
+        };
+        for adjustment in adjustments {
+            self.adjust(adjustment);
+        }
+        Ok(())
+    }
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        for value in &[self.hue, self.saturation, self.brightness, self.kelvin] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    fn decode(bytes: &[u8]) -> Self {
+        Self {
+            hue: u16_at(bytes, 0),
+            saturation: u16_at(bytes, 2),
+            brightness: u16_at(bytes, 4),
+            kelvin: u16_at(bytes, 6),
+        }
+    }
+}
+
+/// The state reported by a light.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LightState {
+    /// The current color.
+    pub color: Hsbk,
+    /// Whether the light is on.
+    pub power: bool,
+    /// The light's label.
+    pub label: String,
+}
+
+impl LightState {
+    /// Encodes the state as the payload of a `STATE` message.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(52);
+        self.color.encode(&mut bytes);
+        bytes.extend_from_slice(&[0; 2]);
+        let power: u16 = if self.power { 65535 } else { 0 };
+        bytes.extend_from_slice(&power.to_le_bytes());
+        let mut label = [0; 32];
+        for (slot, byte) in label.iter_mut().zip(self.label.bytes()) {
+            *slot = byte;
+        }
+        bytes.extend_from_slice(&label);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes
+    }
+    /// Decodes the payload of a `STATE` message.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 52 {
+            return None;
+        }
+        let label = &bytes[12..44];
+        let end = label.iter().position(|b| *b == 0).unwrap_or(label.len());
+        Some(Self {
+            color: Hsbk::decode(bytes),
+            power: u16_at(bytes, 10) != 0,
+            label: String::from_utf8_lossy(&label[..end]).into_owned(),
+        })
+    }
+}
+
+/// Parses a MAC address of the form `d0:73:d5:01:02:03` into a packet target.
+pub fn parse_mac(s: &str) -> Option<[u8; 8]> {
+    let mut target = [0; 8];
+    let mut octets = s.split(&[':', '-'][..]);
+    for slot in target.iter_mut().take(6) {
+        *slot = u8::from_str_radix(octets.next()?, 16).ok()?;
+    }
+    if octets.next().is_some() {
+        None
+    } else {
+        Some(target)
+    }
+}
+
+/// Parses a device address, which may omit the port.
+pub fn parse_address(s: &str) -> Option<SocketAddr> {
+    s.parse()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, PORT)))
+}
+
+/// A LIFX bulb managed over the local network.
+///
+/// Bulbs are addressed by IP address, MAC address, or both; if only a MAC address is known, the
+/// bulb is located by broadcasting a discovery message.
+pub struct LifxLan<'a> {
+    address: Option<&'a str>,
+    mac: Option<&'a str>,
+    broadcast: SocketAddr,
+}
+
+impl<'a> LifxLan<'a> {
+    /// Creates a backend addressing the given bulb.
+    pub fn new(address: Option<&'a str>, mac: Option<&'a str>) -> Self {
+        Self {
+            address,
+            mac,
+            broadcast: SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), PORT),
+        }
+    }
+    /// Sets the address used for discovery broadcasts.
+    pub fn broadcast(mut self, address: SocketAddr) -> Self {
+        self.broadcast = address;
+        self
+    }
+    fn target(&self) -> Result<[u8; 8]> {
+        match self.mac {
+            Some(mac) => parse_mac(mac).ok_or_else(|| Error::InvalidAddress(mac.to_string())),
+            None => Ok([0; 8]),
+        }
+    }
+    fn socket() -> Result<UdpSocket> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_read_timeout(Some(TIMEOUT))?;
+        socket.set_broadcast(true)?;
+        Ok(socket)
+    }
+    /// Sends a packet and waits for a response of the given type.
+    fn exchange(
+        socket: &UdpSocket,
+        to: SocketAddr,
+        packet: &Packet,
+        expect: u16,
+    ) -> Result<(Packet, SocketAddr)> {
+        let bytes = packet.encode();
+        let mut buf = [0; 1024];
+        for _ in 0..ATTEMPTS {
+            socket.send_to(&bytes, to)?;
+            loop {
+                match socket.recv_from(&mut buf) {
+                    Ok((len, from)) => {
+                        if let Some(response) = Packet::decode(&buf[..len]) {
+                            if response.kind == expect
+                                && response.source == packet.source
+                                && response.sequence == packet.sequence
+                            {
+                                return Ok((response, from));
+                            }
+                        }
+                    }
+                    Err(ref err)
+                        if err.kind() == io::ErrorKind::WouldBlock
+                            || err.kind() == io::ErrorKind::TimedOut =>
+                    {
+                        break
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        Err(Error::NoResponse)
+    }
+    /// Finds the address of the bulb, broadcasting a discovery message if necessary.
+    fn locate(&self, socket: &UdpSocket) -> Result<SocketAddr> {
+        if let Some(address) = self.address {
+            return parse_address(address)
+                .ok_or_else(|| Error::InvalidAddress(address.to_string()));
+        }
+        let target = self.target()?;
+        if target == [0; 8] {
+            return Err(Error::InvalidAddress(
+                "no address or MAC address configured".to_string(),
+            ));
+        }
+        let mut packet = Packet::new(kind::GET_SERVICE, target, Vec::new());
+        packet.tagged = false;
+        let (response, from) =
+            Self::exchange(socket, self.broadcast, &packet, kind::STATE_SERVICE)?;
+        match response.payload.as_slice() {
+            [1, ..] if response.payload.len() >= 5 => {
+                let port = u32_at(&response.payload, 1) as u16;
+                Ok(SocketAddr::new(from.ip(), port))
+            }
+            _ => Err(Error::NoResponse),
+        }
+    }
+    /// Sends a message to the bulb, waiting for an acknowledgement unless `fast` is set.
+    fn send(&self, kind: u16, payload: Vec<u8>, fast: bool) -> Result {
+        let socket = Self::socket()?;
+        let address = self.locate(&socket)?;
+        let mut packet = Packet::new(kind, self.target()?, payload);
+        if fast {
+            socket.send_to(&packet.encode(), address)?;
+            Ok(())
+        } else {
+            packet.ack_required = true;
+            Self::exchange(&socket, address, &packet, kind::ACKNOWLEDGEMENT).map(|_| ())
+        }
+    }
+    /// Asks the bulb for its current state.
+    pub fn get(&self) -> Result<LightState> {
+        let socket = Self::socket()?;
+        let address = self.locate(&socket)?;
+        let mut packet = Packet::new(kind::GET, self.target()?, Vec::new());
+        packet.res_required = true;
+        let (response, _) = Self::exchange(&socket, address, &packet, kind::STATE)?;
+        LightState::decode(&response.payload).ok_or(Error::NoResponse)
+    }
+    fn set_power(&self, on: bool, duration: Option<Duration>, fast: bool) -> Result {
+        let level: u16 = if on { 65535 } else { 0 };
+        let mut payload = level.to_le_bytes().to_vec();
+        payload.extend_from_slice(&millis(duration).to_le_bytes());
+        self.send(kind::SET_LIGHT_POWER, payload, fast)
+    }
+    fn waveform(&self, shape: u8, params: &Waveform) -> Result {
+        let mut current = self.get()?.color;
+        if let Some(from) = &params.from {
+            current.apply(from)?;
+            let mut payload = vec![0];
+            current.encode(&mut payload);
+            payload.extend_from_slice(&0u32.to_le_bytes());
+            self.send(kind::SET_COLOR, payload, false)?;
+        }
+        let mut color = current;
+        color.apply(&params.color)?;
+        let mut payload = vec![0, u8::from(!params.persist)];
+        color.encode(&mut payload);
+        let period = params.period.unwrap_or(Duration::from_secs(1));
+        payload.extend_from_slice(&millis(Some(period)).to_le_bytes());
+        payload.extend_from_slice(&f32::from(params.cycles.unwrap_or(1)).to_le_bytes());
+        // The skew ratio maps the peak from [0, 1] onto the full range of an i16.
+        let peak = params.peak.unwrap_or(0.5).clamp(0.0, 1.0);
+        let skew = (peak * 65535.0 - 32768.0).round() as i16;
+        payload.extend_from_slice(&skew.to_le_bytes());
+        payload.push(shape);
+        self.send(kind::SET_WAVEFORM, payload, false)
+    }
+    fn tile_effect(
+        &self,
+        effect: u8,
+        palette: &[Color],
+        period: Option<Duration>,
+        duration: Option<Duration>,
+    ) -> Result {
+        if palette.len() > MAX_PALETTE {
+            return Err(Error::UnsupportedEffect("palettes of more than 16 colors"));
+        }
+        let mut payload = vec![0, 0];
+        payload.extend_from_slice(&0u32.to_le_bytes());
+        payload.push(effect);
+        let period = period.unwrap_or(DEFAULT_EFFECT_PERIOD);
+        payload.extend_from_slice(&millis(Some(period)).to_le_bytes());
+        let nanos = duration.map_or(0, |d| d.as_nanos().min(u64::MAX.into()) as u64);
+        payload.extend_from_slice(&nanos.to_le_bytes());
+        payload.extend_from_slice(&[0; 8 + 32]);
+        payload.push(palette.len() as u8);
+        for color in palette {
+            let mut hsbk = Hsbk {
+                hue: 0,
+                saturation: 65535,
+                brightness: 65535,
+                kelvin: 3500,
+            };
+            hsbk.apply(color)?;
+            hsbk.encode(&mut payload);
+        }
+        payload.resize(payload.len() + 8 * (MAX_PALETTE - palette.len()), 0);
+        self.send(kind::SET_TILE_EFFECT, payload, false)
+    }
+}
+
+impl From<LightState> for DeviceState {
+    fn from(state: LightState) -> Self {
+        let unit = |value: u16| f32::from(value) / 65535.0;
+        Self {
+            power: Some(state.power),
+            hue: Some(unit(state.color.hue) * 360.0),
+            saturation: Some(unit(state.color.saturation)),
+            brightness: Some(unit(state.color.brightness)),
+            kelvin: Some(state.color.kelvin),
+            reachable: true,
+            last_seen: Some(SystemTime::now()),
+        }
+    }
+}
+
+/// Converts a transition duration to the protocol's representation (milliseconds).
+fn millis(duration: Option<Duration>) -> u32 {
+    duration.map_or(0, |d| d.as_millis().min(u32::MAX.into()) as u32)
+}
+
+impl<'a> Backend for LifxLan<'a> {
+    fn power(&self, on: bool, duration: Option<Duration>, fast: bool) -> Result {
+        self.set_power(on, duration, fast)
+    }
+    fn toggle(&self, duration: Option<Duration>) -> Result {
+        let state = self.get()?;
+        self.set_power(!state.power, duration, false)
+    }
+    fn state(&self) -> Result<DeviceState> {
+        match self.get() {
+            Ok(state) => Ok(state.into()),
+            Err(Error::NoResponse) => Ok(DeviceState::default()),
+            Err(err) => Err(err),
+        }
+    }
+    fn set(
+        &self,
+        color: Option<Color>,
+        brightness: Option<f32>,
+        duration: Option<Duration>,
+        fast: bool,
+    ) -> Result {
+        if color.is_none() && brightness.is_none() {
+            return self.set_power(true, duration, fast);
+        }
+        let mut hsbk = self.get()?.color;
+        if let Some(color) = color {
+            hsbk.apply(&color)?;
+        }
+        if let Some(brightness) = brightness {
+            hsbk.adjust(Adjustment::Brightness(brightness));
+        }
+        let mut payload = vec![0];
+        hsbk.encode(&mut payload);
+        payload.extend_from_slice(&millis(duration).to_le_bytes());
+        self.send(kind::SET_COLOR, payload, fast)?;
+        self.set_power(true, duration, fast)
+    }
+    /// Runs an effect; morph and flame are only supported by tiles.
+    fn effect(&self, effect: &Effect) -> Result {
+        match effect {
+            Effect::Breathe(params) => self.waveform(waveform::SINE, params),
+            Effect::Pulse(params) => self.waveform(waveform::PULSE, params),
+            Effect::Morph {
+                palette,
+                period,
+                duration,
+            } => self.tile_effect(tile_effect::MORPH, palette, *period, *duration),
+            Effect::Flame { period, duration } => {
+                self.tile_effect(tile_effect::FLAME, &[], *period, *duration)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const MAC: [u8; 8] = [0xd0, 0x73, 0xd5, 1, 2, 3, 0, 0];
+
+    /// A stand-in for a bulb, which answers the given number of packets.
+    fn bulb(
+        mut state: LightState,
+        packets: usize,
+    ) -> (SocketAddr, thread::JoinHandle<Vec<Packet>>) {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = socket.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut buf = [0; 1024];
+            while received.len() < packets {
+                let (len, from) = socket.recv_from(&mut buf).unwrap();
+                let packet = Packet::decode(&buf[..len]).unwrap();
+                let mut replies = Vec::new();
+                if packet.ack_required {
+                    replies.push(packet.reply(kind::ACKNOWLEDGEMENT, MAC, Vec::new()));
+                }
+                match packet.kind {
+                    kind::GET_SERVICE if packet.target == MAC => {
+                        let mut payload = vec![1];
+                        payload.extend_from_slice(&u32::from(address.port()).to_le_bytes());
+                        replies.push(packet.reply(kind::STATE_SERVICE, MAC, payload));
+                    }
+                    kind::GET => replies.push(packet.reply(kind::STATE, MAC, state.encode())),
+                    kind::SET_LIGHT_POWER => state.power = u16_at(&packet.payload, 0) != 0,
+                    kind::SET_COLOR => state.color = Hsbk::decode(&packet.payload[1..]),
+                    _ => {}
+                }
+                for reply in replies {
+                    socket.send_to(&reply.encode(), from).unwrap();
+                }
+                received.push(packet);
+            }
+            received
+        });
+        (address, handle)
+    }
+
+    fn off() -> LightState {
+        LightState {
+            color: Hsbk {
+                hue: 0,
+                saturation: 0,
+                brightness: 65535,
+                kelvin: 3500,
+            },
+            power: false,
+            label: "Porch".to_string(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut packet = Packet::new(kind::SET_LIGHT_POWER, MAC, vec![0xff, 0xff, 0, 0, 0, 0]);
+        packet.ack_required = true;
+        let bytes = packet.encode();
+        assert_eq!(bytes.len(), 42);
+        assert_eq!(Packet::decode(&bytes), Some(packet));
+        assert!(Packet::decode(&bytes[..40]).is_none());
+        assert_eq!(LightState::decode(&off().encode()), Some(off()));
+    }
+
+    #[test]
+    fn colors() {
+        let mut color = off().color;
+        color.apply(&Color::Blue).unwrap();
+        assert_eq!(color.hue, scale(250.0 / 360.0));
+        assert_eq!(color.saturation, 65535);
+        color.apply(&Color::White).unwrap();
+        assert_eq!(color.saturation, 0);
+        color
+            .apply(&Color::Hsbk(Some(120), Some(0.5), None, Some(2700)))
+            .unwrap();
+        assert_eq!(color.hue, scale(120.0 / 360.0));
+        assert_eq!(color.saturation, scale(0.5));
+        assert_eq!(color.brightness, 65535);
+        assert_eq!(color.kelvin, 2700);
+        color.apply(&Color::Rgb([0, 0, 255])).unwrap();
+        assert_eq!(color.hue, scale(240.0 / 360.0));
+        color.apply(&Color::RgbStr("ff0000".to_string())).unwrap();
+        assert_eq!(color.hue, 0);
+        assert!(color.apply(&Color::Custom("mauve".to_string())).is_err());
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(parse_mac("d0:73:d5:01:02:03"), Some(MAC));
+        assert_eq!(parse_mac("d0-73-d5-01-02-03"), Some(MAC));
+        assert!(parse_mac("d0:73:d5:01:02").is_none());
+        assert!(parse_mac("d0:73:d5:01:02:03:04").is_none());
+        assert_eq!(
+            parse_address("10.0.0.5"),
+            Some(SocketAddr::new([10, 0, 0, 5].into(), PORT))
+        );
+        assert_eq!(
+            parse_address("10.0.0.5:1234"),
+            Some(SocketAddr::new([10, 0, 0, 5].into(), 1234))
+        );
+        assert!(parse_address("porch").is_none());
+    }
+
+    #[test]
+    fn power() {
+        let (address, bulb) = bulb(off(), 1);
+        let address = address.to_string();
+        LifxLan::new(Some(&address), None)
+            .power(true, Some(Duration::from_millis(1500)), false)
+            .unwrap();
+        let packets = bulb.join().unwrap();
+        assert_eq!(packets[0].kind, kind::SET_LIGHT_POWER);
+        assert_eq!(packets[0].payload, vec![0xff, 0xff, 0xdc, 0x05, 0, 0]);
+        assert!(packets[0].tagged);
+    }
+
+    #[test]
+    fn toggle() {
+        let (address, bulb) = bulb(off(), 2);
+        let address = address.to_string();
+        LifxLan::new(Some(&address), None).toggle(None).unwrap();
+        let packets = bulb.join().unwrap();
+        assert_eq!(packets[0].kind, kind::GET);
+        assert_eq!(packets[1].kind, kind::SET_LIGHT_POWER);
+        assert_eq!(u16_at(&packets[1].payload, 0), 65535);
+    }
+
+    #[test]
+    fn set() {
+        let (address, bulb) = bulb(off(), 3);
+        let address = address.to_string();
+        LifxLan::new(Some(&address), None)
+            .set(
+                Some(Color::Custom("hue:120".to_string())),
+                Some(0.5),
+                Some(Duration::from_secs(2)),
+                false,
+            )
+            .unwrap();
+        let packets = bulb.join().unwrap();
+        assert_eq!(packets[1].kind, kind::SET_COLOR);
+        let color = Hsbk::decode(&packets[1].payload[1..]);
+        assert_eq!(color.hue, scale(120.0 / 360.0));
+        assert_eq!(color.brightness, scale(0.5));
+        assert_eq!(color.kelvin, 3500);
+        assert_eq!(u32_at(&packets[1].payload, 9), 2000);
+        assert_eq!(packets[2].kind, kind::SET_LIGHT_POWER);
+        // The bulb was off, so it fades on along with the color.
+        assert_eq!(u32_at(&packets[2].payload, 2), 2000);
+    }
+
+    #[test]
+    fn waveforms() {
+        let (address, bulb) = bulb(off(), 2);
+        let address = address.to_string();
+        let pulse = Effect::Pulse(Waveform {
+            cycles: Some(3),
+            peak: Some(1.0),
+            ..Waveform::new(Color::Custom("hue:120".to_string()))
+        });
+        LifxLan::new(Some(&address), None).effect(&pulse).unwrap();
+        let packets = bulb.join().unwrap();
+        assert_eq!(packets[1].kind, kind::SET_WAVEFORM);
+        let payload = &packets[1].payload;
+        assert_eq!(payload.len(), 21);
+        assert_eq!(payload[1], 1);
+        assert_eq!(Hsbk::decode(&payload[2..]).hue, scale(120.0 / 360.0));
+        assert_eq!(u32_at(payload, 10), 1000);
+        assert_eq!(&payload[14..18], &3.0f32.to_le_bytes());
+        assert_eq!(&payload[18..20], &i16::MAX.to_le_bytes());
+        assert_eq!(payload[20], waveform::PULSE);
+    }
+
+    #[test]
+    fn tile_effects() {
+        let (address, bulb) = bulb(off(), 1);
+        let address = address.to_string();
+        let morph = Effect::Morph {
+            palette: vec![Color::Custom("red".to_string())],
+            period: None,
+            duration: Some(Duration::from_secs(1)),
+        };
+        LifxLan::new(Some(&address), None).effect(&morph).unwrap();
+        let packets = bulb.join().unwrap();
+        assert_eq!(packets[0].kind, kind::SET_TILE_EFFECT);
+        let payload = &packets[0].payload;
+        assert_eq!(payload.len(), 188);
+        assert_eq!(payload[6], tile_effect::MORPH);
+        assert_eq!(u32_at(payload, 7), 3000);
+        assert_eq!(&payload[11..19], &1_000_000_000u64.to_le_bytes());
+        assert_eq!(payload[59], 1);
+        assert_eq!(Hsbk::decode(&payload[60..]).saturation, 65535);
+    }
+
+    #[test]
+    fn state() {
+        let (address, bulb) = bulb(off(), 1);
+        let address = address.to_string();
+        let state = LifxLan::new(Some(&address), None).state().unwrap();
+        bulb.join().unwrap();
+        assert_eq!(state.power, Some(false));
+        assert_eq!(state.brightness, Some(1.0));
+        assert_eq!(state.kelvin, Some(3500));
+        assert!(state.reachable);
+    }
+
+    #[test]
+    fn discovery() {
+        let (address, bulb) = bulb(off(), 2);
+        LifxLan::new(None, Some("d0:73:d5:01:02:03"))
+            .broadcast(address)
+            .power(false, None, false)
+            .unwrap();
+        let packets = bulb.join().unwrap();
+        assert_eq!(packets[0].kind, kind::GET_SERVICE);
+        assert!(!packets[0].tagged);
+        assert_eq!(packets[1].kind, kind::SET_LIGHT_POWER);
+        assert_eq!(packets[1].target, MAC);
+        assert!(!packets[1].tagged);
+    }
+}
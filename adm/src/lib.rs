@@ -3,7 +3,19 @@ extern crate serde_derive;
 
 pub extern crate lifxi;
 
+pub mod action;
+pub mod backend;
+pub mod check;
 pub mod config;
+pub mod context;
 pub mod device;
+pub mod effect;
+pub mod lan;
 pub mod message;
 pub mod parse;
+pub mod rule;
+pub mod scene;
+pub mod schedule;
+pub mod solar;
+
+pub use crate::context::Context;
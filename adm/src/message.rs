@@ -1,68 +1,790 @@
 //! Message objects for transit over the wire.
 
+use std::{fmt, time::Duration};
+
 use lifxi::http::Color;
+use schemars::{
+    schema::{InstanceType, Metadata, RootSchema, Schema, SchemaObject},
+    JsonSchema,
+};
+
+use crate::{
+    backend,
+    context::Context,
+    device::{Delta, Device, DeviceState},
+    effect::Effect,
+};
 
+#[derive(Clone, Debug)]
 pub enum Message {
     /// A message requesting a change in power status.
-    Power { device: String, power: bool },
+    Power {
+        device: String,
+        power: bool,
+        duration: Option<Duration>,
+    },
     /// A message requesting a power toggle.
-    Toggle { device: String },
+    Toggle {
+        device: String,
+        duration: Option<Duration>,
+    },
     /// A message requesting a combined brightness and color setting.
     State {
         device: String,
         color: Option<Color>,
         brightness: Option<f32>,
+        duration: Option<Duration>,
     },
     /// A message requesting a brightness setting.
-    Brightness { device: String, brightness: f32 },
+    Brightness {
+        device: String,
+        brightness: f32,
+        duration: Option<Duration>,
+    },
     /// A message requesting a color setting.
-    Color { device: String, color: Color },
+    Color {
+        device: String,
+        color: Color,
+        duration: Option<Duration>,
+    },
+    /// A message requesting a change in color/brightness relative to the current state.
+    Adjust {
+        device: String,
+        delta: Delta,
+        duration: Option<Duration>,
+    },
+    /// A message requesting that an effect be run.
+    Effect { device: String, effect: Effect },
+    /// A message requesting that a scene be activated.
+    Scene { name: String },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(untagged, rename_all = "lowercase")]
+impl Message {
+    /// The device specifier (a device, group, or list thereof) the message concerns, if any.
+    pub fn device(&self) -> Option<&str> {
+        match self {
+            Message::Power { device, .. }
+            | Message::Toggle { device, .. }
+            | Message::State { device, .. }
+            | Message::Brightness { device, .. }
+            | Message::Color { device, .. }
+            | Message::Adjust { device, .. }
+            | Message::Effect { device, .. } => Some(device),
+            Message::Scene { .. } => None,
+        }
+    }
+    /// Carries out the message's request on the given device.
+    ///
+    /// Scenes span several devices and are activated with [`Scene::apply`](crate::scene::Scene::apply)
+    /// instead, so scene messages are ignored here.
+    pub fn apply(self, context: &Context, device: &Device, fast: bool) -> backend::Result {
+        match self {
+            Message::Power {
+                power, duration, ..
+            } => device.power(context, power, duration, fast),
+            Message::Toggle { duration, .. } => device.toggle(context, duration),
+            Message::State {
+                color,
+                brightness,
+                duration,
+                ..
+            } => device.set(context, color, brightness, duration, fast),
+            Message::Brightness {
+                brightness,
+                duration,
+                ..
+            } => device.set(context, None, Some(brightness), duration, fast),
+            Message::Color {
+                color, duration, ..
+            } => device.set(context, Some(color), None, duration, fast),
+            Message::Adjust {
+                delta, duration, ..
+            } => device.adjust(context, &delta, duration, fast),
+            Message::Effect { effect, .. } => device.effect(context, &effect),
+            Message::Scene { .. } => Ok(()),
+        }
+    }
+}
+
+/// (De)serializes optional durations as (fractional) seconds, as the LIFX API does.
+pub(crate) mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(d) => serializer.serialize_some(&d.as_secs_f64()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        match Option::<f64>::deserialize(deserializer)? {
+            Some(s) => crate::parse::seconds(s).map(Some).ok_or_else(|| {
+                serde::de::Error::custom("durations must be non-negative and finite")
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The version of the payload format written by [`MqttPayload::encode`].
+pub const PAYLOAD_VERSION: u32 = 1;
+
+/// A command payload, as sent to the `devices/<name>/…` topics.
+///
+/// On the wire, payloads are JSON objects tagged with their `type` and the format `version`
+/// (e.g. `{"version": 1, "type": "power", "power": true}`); see `schema/payload.json` for the
+/// full format. Use [`MqttPayload::encode`] and [`MqttPayload::decode`] rather than serializing
+/// payloads directly, so that the version is written and checked.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
 pub enum MqttPayload {
     /// A payload encoding a change in power.
-    Power { power: bool },
+    Power {
+        power: bool,
+        /// How long the change should be faded over, in seconds.
+        #[serde(default, with = "seconds", skip_serializing_if = "Option::is_none")]
+        #[schemars(with = "Option<f64>", range(min = 0))]
+        duration: Option<Duration>,
+    },
     /// A payload encoding a color/brightness setting.
+    State {
+        /// The color, as a LIFX color string (e.g. `red` or `hue:120 saturation:0.5`).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(with = "Option<String>")]
+        color: Option<Color>,
+        /// The brightness, in `[0, 1]`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(range(min = 0, max = 1))]
+        brightness: Option<f32>,
+        /// How long the change should be faded over, in seconds.
+        #[serde(default, with = "seconds", skip_serializing_if = "Option::is_none")]
+        #[schemars(with = "Option<f64>", range(min = 0))]
+        duration: Option<Duration>,
+    },
+    /// A payload encoding a relative color/brightness change.
+    Adjust {
+        delta: Delta,
+        /// How long the change should be faded over, in seconds.
+        #[serde(default, with = "seconds", skip_serializing_if = "Option::is_none")]
+        #[schemars(with = "Option<f64>", range(min = 0))]
+        duration: Option<Duration>,
+    },
+    /// A payload encoding an effect to run.
+    Effect(Effect),
+}
+
+/// The payload format used before payloads were tagged and versioned, still accepted for
+/// compatibility.
+///
+/// Variants reject unknown fields, so inputs matching several variants (e.g. `{"power": true,
+/// "color": null}`) are refused instead of silently matching the first.
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum LegacyPayload {
+    Power {
+        power: bool,
+        #[serde(default, with = "seconds")]
+        duration: Option<Duration>,
+    },
+    Adjust {
+        adjust: Delta,
+        #[serde(default, with = "seconds")]
+        duration: Option<Duration>,
+    },
+    Effect(Effect),
     State {
         color: Option<Color>,
         brightness: Option<f32>,
+        #[serde(default, with = "seconds")]
+        duration: Option<Duration>,
     },
 }
 
+impl From<LegacyPayload> for MqttPayload {
+    fn from(payload: LegacyPayload) -> Self {
+        match payload {
+            LegacyPayload::Power { power, duration } => MqttPayload::Power { power, duration },
+            LegacyPayload::Adjust { adjust, duration } => MqttPayload::Adjust {
+                delta: adjust,
+                duration,
+            },
+            LegacyPayload::Effect(effect) => MqttPayload::Effect(effect),
+            LegacyPayload::State {
+                color,
+                brightness,
+                duration,
+            } => MqttPayload::State {
+                color,
+                brightness,
+                duration,
+            },
+        }
+    }
+}
+
+/// Where to acknowledge a request, for publishers which want to know whether it succeeded.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Reply {
+    /// The topic to publish the [`MqttReply`] to.
+    pub reply_to: String,
+    /// An identifier for the request, echoed back in the reply.
+    pub correlation_id: String,
+}
+
+/// A command payload (if the command takes one) along with where to acknowledge it (if anywhere).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Request {
+    /// The command payload.
+    pub payload: Option<MqttPayload>,
+    /// Where to acknowledge the command.
+    pub reply: Option<Reply>,
+}
+
+/// A request along with the format version, as it appears on the wire.
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "Payload")]
+struct Versioned<'a> {
+    version: u32,
+    #[serde(flatten)]
+    reply: Option<&'a Reply>,
+    #[serde(flatten)]
+    payload: Option<&'a MqttPayload>,
+}
+
+/// The payload published to a request's reply topic once the request has been handled.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MqttReply {
+    /// The request's correlation identifier.
+    pub correlation_id: String,
+    /// Whether the request was carried out successfully.
+    pub success: bool,
+    /// The failures encountered while carrying out the request.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<MqttError>,
+}
+
+impl MqttReply {
+    /// Creates a reply to the given request, which succeeded if there were no failures.
+    pub fn new(reply: &Reply, errors: Vec<MqttError>) -> Self {
+        Self {
+            correlation_id: reply.correlation_id.clone(),
+            success: errors.is_empty(),
+            errors,
+        }
+    }
+}
+
+/// Represents a payload which couldn't be decoded.
+#[derive(Debug)]
+pub enum PayloadError {
+    /// The payload isn't valid JSON, or doesn't match any payload type.
+    Malformed(serde_json::Error),
+    /// The payload was written for a format version this version of `adm` doesn't understand.
+    UnsupportedVersion(serde_json::Value),
+    /// A value was outside of its permitted range.
+    OutOfRange(&'static str, f32),
+    /// A payload was required, but none was given.
+    Missing,
+}
+
+impl From<serde_json::Error> for PayloadError {
+    fn from(err: serde_json::Error) -> Self {
+        PayloadError::Malformed(err)
+    }
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PayloadError::*;
+        match self {
+            Malformed(err) => write!(f, "Malformed payload: {}", err),
+            UnsupportedVersion(version) => write!(f, "Unsupported payload version {}", version),
+            OutOfRange(field, value) => write!(f, "The {} {} is out of range", field, value),
+            Missing => write!(f, "No payload given"),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+impl Request {
+    /// Encodes the request as versioned JSON.
+    pub fn encode(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&Versioned {
+            version: PAYLOAD_VERSION,
+            reply: self.reply.as_ref(),
+            payload: self.payload.as_ref(),
+        })
+    }
+    /// Decodes a JSON request, validating its payload.
+    ///
+    /// An empty string is a request without a payload or reply address. Requests without a
+    /// `version` are read in the legacy (untagged) format, which requires a payload.
+    pub fn decode(s: &str) -> Result<Self, PayloadError> {
+        if s.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let mut value: serde_json::Value = serde_json::from_str(s)?;
+        let (version, reply) = match value.as_object_mut() {
+            Some(object) => {
+                let reply_to = object.remove("reply_to");
+                let correlation_id = object.remove("correlation_id");
+                let reply = match (reply_to, correlation_id) {
+                    (None, None) => None,
+                    (reply_to, correlation_id) => {
+                        Some(serde_json::from_value(serde_json::json!({
+                            "reply_to": reply_to,
+                            "correlation_id": correlation_id,
+                        }))?)
+                    }
+                };
+                (object.remove("version"), reply)
+            }
+            None => (None, None),
+        };
+        let empty = value.as_object().is_some_and(|o| o.is_empty());
+        let payload: Option<MqttPayload> = match version {
+            Some(v) if v == PAYLOAD_VERSION && empty => None,
+            Some(v) if v == PAYLOAD_VERSION => Some(serde_json::from_value(value)?),
+            Some(v) => return Err(PayloadError::UnsupportedVersion(v)),
+            None => Some(serde_json::from_value::<LegacyPayload>(value)?.into()),
+        };
+        if let Some(payload) = &payload {
+            payload.validate()?;
+        }
+        Ok(Self { payload, reply })
+    }
+    /// The JSON schema describing encoded requests.
+    pub fn schema() -> RootSchema {
+        let mut root = schemars::schema_for!(Versioned);
+        let envelope = root.schema.object();
+        envelope.properties.insert(
+            "version".to_string(),
+            Schema::Object(SchemaObject {
+                metadata: Some(Box::new(Metadata {
+                    description: Some("The payload format version.".to_string()),
+                    ..Metadata::default()
+                })),
+                const_value: Some(PAYLOAD_VERSION.into()),
+                ..SchemaObject::default()
+            }),
+        );
+        let envelope = envelope.properties.clone();
+        root.schema.extensions.insert(
+            "dependencies".to_string(),
+            serde_json::json!({
+                "reply_to": ["correlation_id"],
+                "correlation_id": ["reply_to"],
+            }),
+        );
+        // Payload types reject unknown fields, but JSON schema only counts a subschema's own
+        // properties as known, so the envelope's fields are repeated in each type. Effects are
+        // described by nested subschemas instead, so they can't reject unknown fields at this level.
+        let variants = root.schema.subschemas().one_of.get_or_insert_with(Vec::new);
+        for variant in variants.iter_mut() {
+            if let Schema::Object(variant) = variant {
+                if variant.subschemas.is_some() {
+                    variant.object().additional_properties = None;
+                } else {
+                    variant.object().properties.extend(envelope.clone());
+                }
+            }
+        }
+        let mut empty = SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some("A request without a payload (e.g. a toggle).".to_string()),
+                ..Metadata::default()
+            })),
+            instance_type: Some(InstanceType::Object.into()),
+            ..SchemaObject::default()
+        };
+        empty.object().properties = envelope;
+        empty.object().additional_properties = Some(Box::new(Schema::Bool(false)));
+        variants.push(Schema::Object(empty));
+        root
+    }
+}
+
+/// Checks that a value is finite and within the given range.
+fn check(field: &'static str, value: Option<f32>, min: f32, max: f32) -> Result<(), PayloadError> {
+    match value {
+        Some(v) if !(min..=max).contains(&v) => Err(PayloadError::OutOfRange(field, v)),
+        _ => Ok(()),
+    }
+}
+
+impl MqttPayload {
+    /// Encodes the payload as versioned JSON.
+    pub fn encode(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&Versioned {
+            version: PAYLOAD_VERSION,
+            reply: None,
+            payload: Some(self),
+        })
+    }
+    /// Decodes a JSON payload, validating its contents.
+    ///
+    /// See [`Request::decode`] for the accepted formats.
+    pub fn decode(s: &str) -> Result<Self, PayloadError> {
+        Request::decode(s)?.payload.ok_or(PayloadError::Missing)
+    }
+    /// Checks that the payload's values are within their permitted ranges.
+    pub fn validate(&self) -> Result<(), PayloadError> {
+        match self {
+            MqttPayload::State { brightness, .. } => check("brightness", *brightness, 0.0, 1.0),
+            MqttPayload::Adjust { delta, .. } => {
+                check("brightness change", delta.brightness, -1.0, 1.0)?;
+                check("saturation change", delta.saturation, -1.0, 1.0)?;
+                check("hue change", delta.hue, -360.0, 360.0)
+            }
+            MqttPayload::Effect(Effect::Breathe(waveform))
+            | MqttPayload::Effect(Effect::Pulse(waveform)) => {
+                check("peak", waveform.peak, 0.0, 1.0)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The payload published (and retained) to `devices/<name>/state/current` to report a device's
+/// actual state.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MqttState {
+    /// Whether the device is on, if known.
+    pub power: Option<bool>,
+    /// The device's color, if known.
+    pub color: Option<Color>,
+    /// The device's brightness, if known.
+    pub brightness: Option<f32>,
+    /// Whether the device could be reached.
+    pub reachable: bool,
+}
+
+impl From<&DeviceState> for MqttState {
+    fn from(state: &DeviceState) -> Self {
+        let mut components = Vec::new();
+        if let Some(hue) = state.hue {
+            components.push(format!("hue:{}", hue));
+        }
+        if let Some(saturation) = state.saturation {
+            components.push(format!("saturation:{}", saturation));
+        }
+        if let Some(kelvin) = state.kelvin {
+            components.push(format!("kelvin:{}", kelvin));
+        }
+        Self {
+            power: state.power,
+            color: if components.is_empty() {
+                None
+            } else {
+                Some(Color::Custom(components.join(" ")))
+            },
+            brightness: state.brightness,
+            reachable: state.reachable,
+        }
+    }
+}
+
+/// The topic to which a device's actual state is published.
+pub fn state_topic(device: &str) -> String {
+    format!("devices/{}/state/current", device)
+}
+
+/// The kinds of failure reported in an [`MqttError`].
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    /// The payload couldn't be decoded.
+    InvalidPayload,
+    /// The payload was valid, but doesn't belong on the topic it was sent to.
+    UnexpectedPayload,
+    /// No devices matched the specifier.
+    DeviceNotFound,
+    /// No scene with the given name is configured.
+    SceneNotFound,
+    /// The scene's configuration is invalid.
+    InvalidScene,
+    /// A device couldn't carry out the command.
+    Device,
+}
+
+/// The payload published to a command's error topic (see [`error_topic`]) when the command
+/// couldn't be carried out.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MqttError {
+    /// The topic the failed command was sent to.
+    pub topic: String,
+    /// What went wrong.
+    pub kind: ErrorKind,
+    /// The device which failed, if the failure was specific to one device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// A human-readable description of the failure.
+    pub message: String,
+}
+
+impl fmt::Display for MqttError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.device {
+            Some(device) => write!(f, "{}: {}", device, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The topic to which failures of commands sent to the given topic are reported.
+///
+/// Commands sent to `devices/<name>/…` are reported to `devices/<name>/error`, and scene
+/// activations to `scenes/<name>/error`.
+pub fn error_topic(topic: &str) -> Option<String> {
+    let mut parts = topic.split('/');
+    match (parts.next(), parts.next()) {
+        (Some(root @ "devices"), Some(name)) | (Some(root @ "scenes"), Some(name)) => {
+            Some(format!("{}/{}/error", root, name))
+        }
+        _ => None,
+    }
+}
+
 pub type MqttMessage = (String, Option<MqttPayload>);
 
 impl From<Message> for MqttMessage {
     fn from(message: Message) -> Self {
         match message {
-            Message::Power { device, power } => (
+            Message::Power {
+                device,
+                power,
+                duration,
+            } => (
                 format!("devices/{}/power", device),
-                Some(MqttPayload::Power { power }),
+                Some(MqttPayload::Power { power, duration }),
             ),
-            Message::Toggle { device } => (format!("devices/{}/power/toggle", device), None),
-            Message::Brightness { device, brightness } => (
+            Message::Toggle { device, duration } => (
+                format!("devices/{}/power/toggle", device),
+                duration.map(|duration| MqttPayload::State {
+                    color: None,
+                    brightness: None,
+                    duration: Some(duration),
+                }),
+            ),
+            Message::Brightness {
+                device,
+                brightness,
+                duration,
+            } => (
                 format!("devices/{}/brightness", device),
                 Some(MqttPayload::State {
                     brightness: Some(brightness),
                     color: None,
+                    duration,
                 }),
             ),
-            Message::Color { device, color } => (
+            Message::Color {
+                device,
+                color,
+                duration,
+            } => (
                 format!("devices/{}/color", device),
                 Some(MqttPayload::State {
                     color: Some(color),
                     brightness: None,
+                    duration,
                 }),
             ),
             Message::State {
                 device,
                 color,
                 brightness,
+                duration,
             } => (
                 format!("devices/{}/state", device),
-                Some(MqttPayload::State { brightness, color }),
+                Some(MqttPayload::State {
+                    brightness,
+                    color,
+                    duration,
+                }),
+            ),
+            Message::Adjust {
+                device,
+                delta,
+                duration,
+            } => (
+                format!("devices/{}/adjust", device),
+                Some(MqttPayload::Adjust { delta, duration }),
+            ),
+            Message::Effect { device, effect } => (
+                format!("devices/{}/effect", device),
+                Some(MqttPayload::Effect(effect)),
             ),
+            Message::Scene { name } => (format!("scenes/{}/activate", name), None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads() {
+        let power = MqttPayload::Power {
+            power: true,
+            duration: Some(Duration::from_secs(2)),
+        };
+        let encoded = power.encode().unwrap();
+        assert_eq!(
+            encoded,
+            r#"{"version":1,"type":"power","power":true,"duration":2.0}"#
+        );
+        assert_eq!(MqttPayload::decode(&encoded).unwrap(), power);
+        let adjust = MqttPayload::Adjust {
+            delta: Delta {
+                kelvin: Some(-500),
+                ..Delta::default()
+            },
+            duration: None,
+        };
+        let encoded = adjust.encode().unwrap();
+        assert_eq!(
+            encoded,
+            r#"{"version":1,"type":"adjust","delta":{"kelvin":-500}}"#
+        );
+        assert_eq!(MqttPayload::decode(&encoded).unwrap(), adjust);
+        let decode = |s: &str| MqttPayload::decode(s);
+        assert!(decode(r#"{"version":1,"type":"power"}"#).is_err());
+        assert!(decode(r#"{"version":1,"type":"power","power":true,"color":null}"#).is_err());
+        assert!(decode(r#"{"version":1,"type":"dance"}"#).is_err());
+        match decode(r#"{"version":2,"type":"power","power":true}"#) {
+            Err(PayloadError::UnsupportedVersion(_)) => {}
+            other => panic!("Decoded {:?}", other),
+        }
+        match decode(r#"{"version":1,"type":"state","brightness":1.5}"#) {
+            Err(PayloadError::OutOfRange("brightness", _)) => {}
+            other => panic!("Decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn requests() {
+        let reply = Reply {
+            reply_to: "adm/replies/1".to_string(),
+            correlation_id: "1".to_string(),
+        };
+        let request = Request {
+            payload: None,
+            reply: Some(reply.clone()),
+        };
+        let encoded = request.encode().unwrap();
+        assert_eq!(
+            encoded,
+            r#"{"version":1,"reply_to":"adm/replies/1","correlation_id":"1"}"#
+        );
+        assert_eq!(Request::decode(&encoded).unwrap(), request);
+        let request = Request {
+            payload: Some(MqttPayload::Power {
+                power: true,
+                duration: None,
+            }),
+            reply: Some(reply.clone()),
+        };
+        assert_eq!(
+            Request::decode(&request.encode().unwrap()).unwrap(),
+            request
+        );
+        assert_eq!(Request::decode("").unwrap(), Request::default());
+        assert!(Request::decode(r#"{"version":1,"reply_to":"adm/replies/1"}"#).is_err());
+        assert!(MqttPayload::decode(r#"{"version":1}"#).is_err());
+        for duration in &["1e300", "-1"] {
+            let payload = format!(
+                r#"{{"version":1,"type":"power","power":true,"duration":{}}}"#,
+                duration
+            );
+            assert!(Request::decode(&payload).is_err());
+        }
+        let reply = MqttReply::new(&reply, Vec::new());
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            r#"{"correlation_id":"1","success":true}"#
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error_topic("devices/porch/power/toggle").as_deref(),
+            Some("devices/porch/error")
+        );
+        assert_eq!(
+            error_topic("scenes/movie/activate").as_deref(),
+            Some("scenes/movie/error")
+        );
+        assert!(error_topic("adm/availability").is_none());
+        let error = MqttError {
+            topic: "devices/all/power".to_string(),
+            kind: ErrorKind::DeviceNotFound,
+            device: None,
+            message: "No devices found".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"topic":"devices/all/power","kind":"device-not-found","message":"No devices found"}"#
+        );
+    }
+
+    #[test]
+    fn legacy() {
+        let decode = |s: &str| MqttPayload::decode(s);
+        assert_eq!(
+            decode(r#"{"power": false, "duration": 1}"#).unwrap(),
+            MqttPayload::Power {
+                power: false,
+                duration: Some(Duration::from_secs(1)),
+            }
+        );
+        assert_eq!(
+            decode(r#"{"brightness": 0.5}"#).unwrap(),
+            MqttPayload::State {
+                color: None,
+                brightness: Some(0.5),
+                duration: None,
+            }
+        );
+        assert_eq!(
+            decode(r#"{"adjust": {"brightness": -0.1}}"#).unwrap(),
+            MqttPayload::Adjust {
+                delta: Delta {
+                    brightness: Some(-0.1),
+                    ..Delta::default()
+                },
+                duration: None,
+            }
+        );
+        assert!(decode(r#"{"power": true, "color": null}"#).is_err());
+        assert!(decode(r#"{"brightness": -1}"#).is_err());
+        assert!(decode("on").is_err());
+    }
+
+    /// The schema shipped for integrators must match the types; set `ADM_UPDATE_SCHEMA` to
+    /// regenerate it.
+    #[test]
+    fn schema() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/schema/payload.json");
+        let schema = serde_json::to_string_pretty(&Request::schema()).unwrap() + "\n";
+        if std::env::var_os("ADM_UPDATE_SCHEMA").is_some() {
+            std::fs::write(path, &schema).unwrap();
         }
+        let shipped = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            shipped == schema,
+            "schema/payload.json is out of date; rerun with ADM_UPDATE_SCHEMA=1"
+        );
     }
 }
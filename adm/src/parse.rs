@@ -1 +1,233 @@
 //! Shared utilities for validating/parsing user input.
+
+use std::time::Duration;
+
+use lifxi::http::Color;
+
+/// A single color adjustment, as understood by the LIFX color string format.
+///
+/// Hues are in degrees; saturation and brightness are in `[0, 1]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Adjustment {
+    /// Sets the hue, in degrees.
+    Hue(f32),
+    /// Sets the saturation.
+    Saturation(f32),
+    /// Sets the brightness.
+    Brightness(f32),
+    /// Sets the color temperature, in Kelvin.
+    Kelvin(u16),
+}
+
+/// Looks up the hue of a named color, as defined by LIFX.
+///
+/// White is special-cased by [`color`], since it has no hue.
+pub fn named_hue(name: &str) -> Option<f32> {
+    let hue = match name.to_ascii_lowercase().as_str() {
+        "red" => 0.0,
+        "orange" => 36.0,
+        "yellow" => 60.0,
+        "green" => 120.0,
+        "cyan" => 180.0,
+        "blue" => 250.0,
+        "purple" => 280.0,
+        "pink" => 325.0,
+        _ => return None,
+    };
+    Some(hue)
+}
+
+/// Converts an RGB triple to hue/saturation/brightness adjustments.
+pub fn rgb(r: u8, g: u8, b: u8) -> Vec<Adjustment> {
+    let (r, g, b) = (
+        f32::from(r) / 255.0,
+        f32::from(g) / 255.0,
+        f32::from(b) / 255.0,
+    );
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    vec![
+        Adjustment::Hue(hue),
+        Adjustment::Saturation(saturation),
+        Adjustment::Brightness(max),
+    ]
+}
+
+fn hex(s: &str) -> Option<Vec<Adjustment>> {
+    if s.len() != 6 || !s.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).ok();
+    Some(rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+fn unit(s: &str) -> Option<f32> {
+    s.parse().ok().filter(|v| (0.0..=1.0).contains(v))
+}
+
+fn component(s: &str) -> Option<Vec<Adjustment>> {
+    use self::Adjustment::*;
+    if s.eq_ignore_ascii_case("white") {
+        return Some(vec![Saturation(0.0)]);
+    }
+    if let Some(hue) = named_hue(s) {
+        return Some(vec![Hue(hue), Saturation(1.0)]);
+    }
+    if let Some(digits) = s.strip_prefix('#') {
+        return hex(digits);
+    }
+    let mut parts = s.splitn(2, ':');
+    let key = parts.next()?.to_ascii_lowercase();
+    let value = parts.next()?;
+    match key.as_str() {
+        "hue" => value
+            .parse()
+            .ok()
+            .filter(|v| (0.0..=360.0).contains(v))
+            .map(|v| vec![Hue(v)]),
+        "saturation" => unit(value).map(|v| vec![Saturation(v)]),
+        "brightness" => unit(value).map(|v| vec![Brightness(v)]),
+        "kelvin" => value
+            .parse()
+            .ok()
+            .filter(|v| (1500..=9000).contains(v))
+            .map(|v| vec![Kelvin(v)]),
+        "rgb" => {
+            let channels = value
+                .split(',')
+                .map(|c| c.trim().parse().ok())
+                .collect::<Option<Vec<u8>>>()?;
+            match channels.as_slice() {
+                [r, g, b] => Some(rgb(*r, *g, *b)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Parses a LIFX-style color string into the adjustments it describes.
+///
+/// Accepted components are named colors (`red`, `white`, …), hex triples (`#ff8000`),
+/// `rgb:255,128,0`, and `hue:`, `saturation:`, `brightness:`, and `kelvin:` prefixed values.
+/// Several components may be combined, separated by whitespace (e.g. `hue:120 saturation:0.5`).
+pub fn color(s: &str) -> Option<Vec<Adjustment>> {
+    let mut adjustments = Vec::new();
+    for part in s.split_whitespace() {
+        adjustments.extend(component(part)?);
+    }
+    if adjustments.is_empty() {
+        None
+    } else {
+        Some(adjustments)
+    }
+}
+
+/// Parses and validates a user-supplied color string into a LIFX color.
+///
+/// See [`color`] for the accepted formats.
+pub fn lifx_color(s: &str) -> Option<Color> {
+    color(s).map(|_| Color::Custom(s.trim().to_ascii_lowercase()))
+}
+
+/// Parses a brightness percentage (e.g. `50%` or `50`) into a brightness in `[0, 1]`.
+pub fn brightness(s: &str) -> Option<f32> {
+    let s = s.trim();
+    let percent = s.strip_suffix('%').unwrap_or(s).trim();
+    percent
+        .parse::<f32>()
+        .ok()
+        .filter(|p| (0.0..=100.0).contains(p))
+        .map(|p| p / 100.0)
+}
+
+/// Converts a number of seconds to a duration, if it's non-negative and not too large to represent.
+pub fn seconds(s: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(s).ok()
+}
+
+/// Parses a duration such as `5s`, `500ms`, `1.5m`, or `1h`.
+///
+/// Bare numbers are interpreted as seconds.
+pub fn duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value.parse::<f64>().ok()?;
+    let scale = match unit.trim() {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+    seconds(value * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Adjustment::*;
+    use super::*;
+    #[test]
+    fn colors() {
+        assert_eq!(color("red"), Some(vec![Hue(0.0), Saturation(1.0)]));
+        assert_eq!(color("White"), Some(vec![Saturation(0.0)]));
+        assert_eq!(color("kelvin:2700"), Some(vec![Kelvin(2700)]));
+        assert_eq!(
+            color("hue:120 saturation:0.5"),
+            Some(vec![Hue(120.0), Saturation(0.5)])
+        );
+        assert_eq!(
+            color("#00ff00"),
+            Some(vec![Hue(120.0), Saturation(1.0), Brightness(1.0)])
+        );
+        assert_eq!(color("rgb:0,0,255"), color("#0000ff"));
+        assert!(color("").is_none());
+        assert!(color("chartreuse").is_none());
+        assert!(color("hue:400").is_none());
+        assert!(color("brightness:2").is_none());
+        assert!(color("kelvin:100").is_none());
+        assert!(color("#12345").is_none());
+        assert!(color("red blorp").is_none());
+    }
+    #[test]
+    fn brightnesses() {
+        assert_eq!(brightness("50%"), Some(0.5));
+        assert_eq!(brightness("100"), Some(1.0));
+        assert_eq!(brightness(" 0 % "), Some(0.0));
+        assert!(brightness("101%").is_none());
+        assert!(brightness("-5%").is_none());
+        assert!(brightness("bright").is_none());
+    }
+    #[test]
+    fn durations() {
+        assert_eq!(duration("5s"), Some(Duration::from_secs(5)));
+        assert_eq!(duration("5"), Some(Duration::from_secs(5)));
+        assert_eq!(duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(duration("1.5m"), Some(Duration::from_secs(90)));
+        assert_eq!(duration("1h"), Some(Duration::from_secs(3600)));
+        assert!(duration("").is_none());
+        assert!(duration("5 fortnights").is_none());
+        assert!(duration("-5s").is_none());
+        assert!(duration("NaN").is_none());
+        assert!(duration("99999999999999999999999h").is_none());
+        assert_eq!(seconds(1.5), Some(Duration::from_millis(1500)));
+        assert!(seconds(-1.0).is_none());
+        assert!(seconds(f64::NAN).is_none());
+        assert!(seconds(f64::INFINITY).is_none());
+        assert!(seconds(1e300).is_none());
+    }
+}
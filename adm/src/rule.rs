@@ -0,0 +1,425 @@
+//! Rules: automations triggered by incoming MQTT messages.
+//!
+//! A rule fires when a message matching its `topic` (and optionally `payload`) arrives, or when a
+//! device `turns-on` or `turns-off`. Rules can be limited to a time of day (`after`/`before`) and
+//! to when devices are on or off (`if-on`/`if-off`).
+
+use std::{collections::HashMap, fmt};
+
+use chrono::NaiveTime;
+use serde_json::{Map, Value};
+
+use crate::{
+    action,
+    action::Action,
+    config::Config,
+    device::Device,
+    message::{state_topic, Message, MqttState},
+};
+
+/// What a payload must look like for a rule to fire.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Pattern {
+    /// The payload must be exactly this string (ignoring surrounding whitespace).
+    Exact(String),
+    /// The payload must be a JSON object with (at least) these fields.
+    Fields(Map<String, Value>),
+}
+
+impl Pattern {
+    /// Whether the given payload matches the pattern.
+    pub fn matches(&self, payload: &str) -> bool {
+        match self {
+            Pattern::Exact(s) => payload.trim() == s,
+            Pattern::Fields(fields) => match serde_json::from_str::<Value>(payload) {
+                Ok(Value::Object(object)) => fields.iter().all(|(k, v)| object.get(k) == Some(v)),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Whether a topic matches an MQTT topic filter (which may contain `+` and `#` wildcards).
+pub fn matches(filter: &str, topic: &str) -> bool {
+    let mut topic = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(t)) if level == t => {}
+            _ => return false,
+        }
+    }
+    topic.next().is_none()
+}
+
+/// A configured automation which fires in response to MQTT messages.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Rule {
+    /// A name for the rule.
+    pub name: Option<String>,
+    /// The topic filter to listen on.
+    pub topic: Option<String>,
+    /// What the payload must look like (any payload matches if omitted).
+    pub payload: Option<Pattern>,
+    /// A device which, when it turns on, fires the rule.
+    pub turns_on: Option<String>,
+    /// A device which, when it turns off, fires the rule.
+    pub turns_off: Option<String>,
+    /// The time of day after which the rule applies (e.g. `18:00`).
+    pub after: Option<String>,
+    /// The time of day before which the rule applies (e.g. `06:00`).
+    pub before: Option<String>,
+    /// Devices which must all be on for the rule to apply.
+    pub if_on: Option<String>,
+    /// Devices which must all be off for the rule to apply.
+    pub if_off: Option<String>,
+    /// What to do when the rule fires.
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Represents an error in the definition of a rule.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// None (or more than one) of `topic`, `turns-on`, and `turns-off` was given.
+    Trigger,
+    /// A payload pattern was given without a topic.
+    Payload,
+    /// The topic filter is invalid.
+    InvalidTopic(String),
+    /// A time couldn't be parsed.
+    InvalidTime(String),
+    /// No devices matched the given specifier.
+    DeviceNotFound(String),
+    /// The rule's action is invalid.
+    Action(action::Error),
+}
+
+impl From<action::Error> for Error {
+    fn from(err: action::Error) -> Self {
+        Error::Action(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            Trigger => write!(
+                f,
+                "Exactly one of topic, turns-on, and turns-off must be given"
+            ),
+            Payload => write!(f, "Payload patterns require a topic"),
+            InvalidTopic(topic) => write!(f, "Invalid topic filter {}", topic),
+            InvalidTime(time) => write!(f, "Invalid time {}", time),
+            DeviceNotFound(device) => write!(f, "No devices found matching specifier {}", device),
+            Action(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn time(s: &Option<String>) -> Result<Option<NaiveTime>, Error> {
+    match s {
+        Some(s) => NaiveTime::parse_from_str(s, "%H:%M")
+            .map(Some)
+            .map_err(|_| Error::InvalidTime(s.clone())),
+        None => Ok(None),
+    }
+}
+
+fn valid_filter(filter: &str) -> bool {
+    let levels = filter.split('/').collect::<Vec<_>>();
+    !filter.is_empty()
+        && levels.iter().enumerate().all(|(i, level)| {
+            (*level == "#" && i == levels.len() - 1)
+                || *level == "+"
+                || !(level.contains('#') || level.contains('+'))
+        })
+}
+
+/// What fires a rule.
+enum Trigger {
+    /// A message on a matching topic with a matching payload.
+    Topic(String, Option<Pattern>),
+    /// The named device changing to the given power state.
+    Power(String, bool),
+}
+
+/// A rule, parsed and ready for evaluation.
+struct Entry {
+    trigger: Trigger,
+    after: Option<NaiveTime>,
+    before: Option<NaiveTime>,
+    if_on: Option<String>,
+    if_off: Option<String>,
+    message: Message,
+}
+
+impl Entry {
+    fn new(rule: &Rule, config: &Config) -> Result<Self, Error> {
+        let device = |spec: &String| {
+            config
+                .find(spec)
+                .map(|d| d.name.clone())
+                .ok_or_else(|| Error::DeviceNotFound(spec.clone()))
+        };
+        let trigger = match (&rule.topic, &rule.turns_on, &rule.turns_off) {
+            (Some(topic), None, None) if valid_filter(topic) => {
+                Trigger::Topic(topic.clone(), rule.payload.clone())
+            }
+            (Some(topic), None, None) => return Err(Error::InvalidTopic(topic.clone())),
+            (None, Some(on), None) => Trigger::Power(device(on)?, true),
+            (None, None, Some(off)) => Trigger::Power(device(off)?, false),
+            _ => return Err(Error::Trigger),
+        };
+        if rule.payload.is_some() && rule.topic.is_none() {
+            return Err(Error::Payload);
+        }
+        for spec in rule.if_on.iter().chain(rule.if_off.iter()) {
+            if config.resolve(spec).is_empty() {
+                return Err(Error::DeviceNotFound(spec.clone()));
+            }
+        }
+        Ok(Self {
+            trigger,
+            after: time(&rule.after)?,
+            before: time(&rule.before)?,
+            if_on: rule.if_on.clone(),
+            if_off: rule.if_off.clone(),
+            message: rule.action.message()?,
+        })
+    }
+    /// Whether the given time of day falls in the rule's window, which may wrap around midnight.
+    fn in_window(&self, now: NaiveTime) -> bool {
+        match (self.after, self.before) {
+            (Some(after), Some(before)) if after <= before => after <= now && now < before,
+            (Some(after), Some(before)) => after <= now || now < before,
+            (Some(after), None) => after <= now,
+            (None, Some(before)) => now < before,
+            (None, None) => true,
+        }
+    }
+}
+
+/// Evaluates a set of rules against incoming MQTT messages.
+pub struct Engine<'a> {
+    config: &'a Config,
+    entries: Vec<Entry>,
+    /// The last power state seen for each device, by name.
+    powers: HashMap<String, bool>,
+}
+
+impl<'a> Engine<'a> {
+    /// Parses and validates the given rules.
+    pub fn new(rules: &[Rule], config: &'a Config) -> Result<Self, Error> {
+        let entries = rules
+            .iter()
+            .map(|rule| Entry::new(rule, config))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            config,
+            entries,
+            powers: HashMap::new(),
+        })
+    }
+    /// The topic filters the rules listen on.
+    pub fn topics(&self) -> Vec<String> {
+        let mut topics = Vec::new();
+        for entry in &self.entries {
+            let topic = match &entry.trigger {
+                Trigger::Topic(topic, _) => topic.clone(),
+                Trigger::Power(..) => state_topic("+"),
+            };
+            if !topics.contains(&topic) {
+                topics.push(topic);
+            }
+        }
+        topics
+    }
+    /// Records a device's reported state, returning its new power state if it changed.
+    ///
+    /// The first state seen for a device isn't considered a change, so retained states don't fire
+    /// rules on startup.
+    fn observe(&mut self, topic: &str, payload: &str) -> Option<(String, bool)> {
+        let device = self
+            .config
+            .devices
+            .iter()
+            .find(|device| state_topic(&device.name) == topic)?;
+        let power = serde_json::from_str::<MqttState>(payload).ok()?.power?;
+        match self.powers.insert(device.name.clone(), power) {
+            Some(previous) if previous != power => Some((device.name.clone(), power)),
+            _ => None,
+        }
+    }
+    /// Evaluates the rules against a message, returning the messages of the rules which fire.
+    ///
+    /// `now` is the current local time of day, and `power` reports whether a device is on (if
+    /// known); both are used to check the rules' conditions.
+    pub fn evaluate<F>(
+        &mut self,
+        topic: &str,
+        payload: &str,
+        now: NaiveTime,
+        power: F,
+    ) -> Vec<Message>
+    where
+        F: Fn(&Device) -> Option<bool>,
+    {
+        let changed = self.observe(topic, payload);
+        let config = self.config;
+        let all = |spec: &Option<String>, on: bool| match spec {
+            Some(spec) => config
+                .resolve(spec)
+                .into_iter()
+                .all(|device| power(device) == Some(on)),
+            None => true,
+        };
+        self.entries
+            .iter()
+            .filter(|entry| match &entry.trigger {
+                Trigger::Topic(filter, pattern) => {
+                    matches(filter, topic)
+                        && match pattern {
+                            Some(pattern) => pattern.matches(payload),
+                            None => true,
+                        }
+                }
+                Trigger::Power(device, on) => changed.as_ref() == Some(&(device.clone(), *on)),
+            })
+            .filter(|entry| entry.in_window(now))
+            .filter(|entry| all(&entry.if_on, true) && all(&entry.if_off, false))
+            .map(|entry| entry.message.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "[[devices]]\ntype=\"lifx\"\nname=\"hall\"\nselector=\"label:hall\"\n[[devices]]\ntype=\"lifx\"\nname=\"porch\"\nselector=\"label:porch\"\nalternatives=[\"front\"]\n";
+
+    fn config(rules: &str) -> Config {
+        toml::from_str(&format!("{}{}", CONFIG, rules)).expect("Failed to parse config.")
+    }
+
+    fn hm(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn filters() {
+        assert!(matches("a/b/c", "a/b/c"));
+        assert!(matches("a/+/c", "a/b/c"));
+        assert!(matches("a/#", "a/b/c"));
+        assert!(matches("#", "a"));
+        assert!(!matches("a/+", "a/b/c"));
+        assert!(!matches("a/b/c/d", "a/b/c"));
+        assert!(!matches("a/b", "a/c"));
+        assert!(valid_filter("a/+/#"));
+        assert!(!valid_filter("a/#/b"));
+        assert!(!valid_filter("a/b+"));
+        assert!(!valid_filter(""));
+    }
+
+    #[test]
+    fn patterns() {
+        let config = config("[[rules]]\ntopic=\"sensors/hall\"\npayload={occupancy=true}\ndevice=\"hall\"\npower=true\n[[rules]]\ntopic=\"buttons/+\"\npayload=\"single\"\ndevice=\"porch\"\ntoggle=true\n");
+        let rules = config.rules.as_ref().unwrap();
+        let mut engine = Engine::new(rules, &config).unwrap();
+        assert_eq!(engine.topics(), vec!["sensors/hall", "buttons/+"]);
+        let mut evaluate = |topic, payload| engine.evaluate(topic, payload, hm("12:00"), |_| None);
+        match evaluate("sensors/hall", "{\"occupancy\": true, \"battery\": 90}").as_slice() {
+            [Message::Power { device, power, .. }] => assert!(device == "hall" && *power),
+            m => panic!("Unexpected messages {:?}", m),
+        }
+        assert!(evaluate("sensors/hall", "{\"occupancy\": false}").is_empty());
+        assert!(evaluate("sensors/hall", "occupied").is_empty());
+        assert_eq!(evaluate("buttons/porch", " single\n").len(), 1);
+        assert!(evaluate("buttons/porch", "double").is_empty());
+        assert!(evaluate("sensors/porch", "single").is_empty());
+    }
+
+    #[test]
+    fn conditions() {
+        let config = config("[[rules]]\ntopic=\"sensors/hall\"\nafter=\"22:00\"\nbefore=\"06:00\"\nif-off=\"porch\"\ndevice=\"hall\"\nbrightness=0.1\n");
+        let rules = config.rules.as_ref().unwrap();
+        let mut engine = Engine::new(rules, &config).unwrap();
+        let off = |_: &Device| Some(false);
+        assert_eq!(
+            engine.evaluate("sensors/hall", "", hm("23:00"), off).len(),
+            1
+        );
+        assert_eq!(
+            engine.evaluate("sensors/hall", "", hm("05:59"), off).len(),
+            1
+        );
+        assert!(engine
+            .evaluate("sensors/hall", "", hm("12:00"), off)
+            .is_empty());
+        assert!(engine
+            .evaluate("sensors/hall", "", hm("23:00"), |_| Some(true))
+            .is_empty());
+        assert!(engine
+            .evaluate("sensors/hall", "", hm("23:00"), |_| None)
+            .is_empty());
+    }
+
+    #[test]
+    fn power_changes() {
+        let config = config("[[rules]]\nturns-on=\"front\"\ndevice=\"hall\"\npower=true\n");
+        let rules = config.rules.as_ref().unwrap();
+        let mut engine = Engine::new(rules, &config).unwrap();
+        assert_eq!(engine.topics(), vec!["devices/+/state/current"]);
+        let topic = state_topic("porch");
+        let state = |on: bool| format!("{{\"power\": {}, \"reachable\": true}}", on);
+        let mut evaluate = |payload: String| {
+            engine
+                .evaluate(&topic, &payload, hm("12:00"), |_| None)
+                .len()
+        };
+        assert_eq!(evaluate(state(true)), 0);
+        assert_eq!(evaluate(state(true)), 0);
+        assert_eq!(evaluate(state(false)), 0);
+        assert_eq!(evaluate(state(true)), 1);
+        assert_eq!(evaluate(state(true)), 0);
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |rules: &str| {
+            let config = config(rules);
+            Engine::new(config.rules.as_ref().unwrap(), &config).err()
+        };
+        assert_eq!(
+            error("[[rules]]\ndevice=\"hall\"\npower=true\n"),
+            Some(Error::Trigger)
+        );
+        assert_eq!(
+            error("[[rules]]\nturns-on=\"hall\"\npayload=\"x\"\ndevice=\"hall\"\npower=true\n"),
+            Some(Error::Payload)
+        );
+        assert_eq!(
+            error("[[rules]]\ntopic=\"a/#/b\"\ndevice=\"hall\"\npower=true\n"),
+            Some(Error::InvalidTopic("a/#/b".to_string()))
+        );
+        assert_eq!(
+            error("[[rules]]\nturns-off=\"attic\"\ndevice=\"hall\"\npower=true\n"),
+            Some(Error::DeviceNotFound("attic".to_string()))
+        );
+        assert_eq!(
+            error("[[rules]]\ntopic=\"a\"\nafter=\"dusk\"\ndevice=\"hall\"\npower=true\n"),
+            Some(Error::InvalidTime("dusk".to_string()))
+        );
+        assert_eq!(
+            error("[[rules]]\ntopic=\"a\"\ndevice=\"hall\"\n"),
+            Some(Error::Action(action::Error::Empty))
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Scenes: named presets applied to several devices at once.
+
+use std::fmt;
+
+use crate::{
+    backend::{self, Backend},
+    config::Config,
+    context::Context,
+    device::Device,
+    parse,
+};
+
+/// A named preset for a set of devices.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Scene {
+    /// The scene name.
+    pub name: String,
+    /// The devices the scene applies to, by name, alias, or group.
+    pub devices: Vec<String>,
+    /// The power state the devices should be in.
+    pub power: Option<bool>,
+    /// The color the devices should be set to (e.g. `red` or `kelvin:2700`).
+    pub color: Option<String>,
+    /// The brightness the devices should be set to, in `[0, 1]`.
+    pub brightness: Option<f32>,
+    /// How long to fade into the scene over (e.g. `5s`).
+    pub duration: Option<String>,
+}
+
+/// Represents an error encountered while applying a scene to a device.
+#[derive(Debug)]
+pub enum Error {
+    /// No devices matched the given specifier.
+    DeviceNotFound(String),
+    /// The scene's color couldn't be parsed.
+    InvalidColor(String),
+    /// The scene's brightness was out of range.
+    InvalidBrightness(f32),
+    /// The scene's duration couldn't be parsed.
+    InvalidDuration(String),
+    /// The device backend encountered an error.
+    Backend(backend::Error),
+}
+
+impl From<backend::Error> for Error {
+    fn from(err: backend::Error) -> Self {
+        Error::Backend(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            DeviceNotFound(device) => write!(f, "No devices found matching specifier {}", device),
+            InvalidColor(color) => write!(f, "Unrecognized color {}", color),
+            InvalidBrightness(brightness) => write!(
+                f,
+                "Brightness {} out of range (expected 0 to 1)",
+                brightness
+            ),
+            InvalidDuration(duration) => write!(f, "Unrecognized duration {}", duration),
+            Backend(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The outcome of applying a scene.
+#[derive(Debug, Default)]
+pub struct Report<'a> {
+    /// The devices the scene was applied to successfully.
+    pub applied: Vec<&'a Device>,
+    /// The devices (or scene settings) which failed, along with the reason.
+    pub failures: Vec<(String, Error)>,
+}
+
+impl<'a> Report<'a> {
+    /// Whether the scene was applied to every device without error.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Scene {
+    /// Applies the scene's settings to a single device through the given backend.
+    pub fn apply_with(&self, backend: &dyn Backend, fast: bool) -> Result<(), Error> {
+        let color = match &self.color {
+            Some(c) => Some(parse::lifx_color(c).ok_or_else(|| Error::InvalidColor(c.clone()))?),
+            None => None,
+        };
+        if let Some(b) = self.brightness.filter(|b| !(0.0..=1.0).contains(b)) {
+            return Err(Error::InvalidBrightness(b));
+        }
+        let duration = match &self.duration {
+            Some(d) => Some(parse::duration(d).ok_or_else(|| Error::InvalidDuration(d.clone()))?),
+            None => None,
+        };
+        match (self.power, color, self.brightness) {
+            (Some(false), _, _) => Device::power_with(backend, false, duration, fast)?,
+            (_, None, None) => {
+                if self.power == Some(true) {
+                    Device::power_with(backend, true, duration, fast)?;
+                }
+            }
+            (_, color, brightness) => Device::set_with(backend, color, brightness, duration, fast)?,
+        }
+        Ok(())
+    }
+    /// Resolves the scene's devices, each at most once, along with any specifiers which matched
+    /// nothing.
+    pub fn resolve<'a>(&self, config: &'a Config) -> (Vec<&'a Device>, Vec<&str>) {
+        let mut devices: Vec<&Device> = Vec::new();
+        let mut missing = Vec::new();
+        for name in &self.devices {
+            let matched = config.resolve(name);
+            if matched.is_empty() {
+                missing.push(name.as_str());
+            }
+            for device in matched {
+                if !devices.iter().any(|d| std::ptr::eq(*d, device)) {
+                    devices.push(device);
+                }
+            }
+        }
+        (devices, missing)
+    }
+    /// Applies the scene to each of its devices, collecting any failures along the way.
+    pub fn apply<'a>(&self, context: &'a Context, fast: bool) -> Report<'a> {
+        let mut report = Report::default();
+        let (devices, missing) = self.resolve(context.config());
+        for name in missing {
+            report
+                .failures
+                .push((name.to_string(), Error::DeviceNotFound(name.to_string())));
+        }
+        for device in devices {
+            let result = device
+                .r#type
+                .backend(context)
+                .map_err(Error::from)
+                .and_then(|backend| self.apply_with(&*backend, fast));
+            match result {
+                Ok(()) => report.applied.push(device),
+                Err(err) => report.failures.push((device.name.clone(), err)),
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::{Call, Fake};
+
+    #[test]
+    fn report() {
+        let config = toml::from_str::<Config>("[[devices]]\ntype=\"lifx\"\nname=\"foo\"\nselector=\"label:foo\"\n[[scenes]]\nname=\"movie\"\ndevices=[\"bar\"]\npower=false\n").expect("Failed to parse config.");
+        let scene = config.scene("Movie").expect("Failed to find scene.");
+        assert_eq!(scene.devices, vec!["bar".to_string()]);
+        let context = Context::new(config.clone());
+        let report = scene.apply(&context, true);
+        assert!(!report.is_success());
+        assert!(report.applied.is_empty());
+        match report.failures.as_slice() {
+            [(name, Error::DeviceNotFound(_))] => assert_eq!(name, "bar"),
+            failures => panic!("Unexpected failures {:?}", failures),
+        }
+        assert!(config.scene("party").is_none());
+    }
+
+    #[test]
+    fn applied() {
+        let config = toml::from_str::<Config>("[[devices]]\ntype=\"lifx-lan\"\nname=\"lamp\"\naddress=\"10.0.0.5\"\n[[devices]]\ntype=\"lifx-lan\"\nname=\"porch\"\naddress=\"10.0.0.6\"\n[[groups]]\nname=\"downstairs\"\ndevices=[\"lamp\", \"porch\"]\n[[scenes]]\nname=\"evening\"\ndevices=[\"downstairs\", \"lamp\"]\n").expect("Failed to parse config.");
+        let scene = config.scene("evening").expect("Failed to find scene.");
+        let (devices, missing) = scene.resolve(&config);
+        let names: Vec<_> = devices.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["lamp", "porch"]);
+        assert!(missing.is_empty());
+        // The scene doesn't change anything, so applying it never reaches the bulbs.
+        let context = Context::new(config.clone());
+        let report = scene.apply(&context, true);
+        assert!(report.is_success());
+        assert_eq!(report.applied, devices);
+    }
+
+    #[test]
+    fn settings() {
+        let mut scene = Scene {
+            name: "reading".to_string(),
+            devices: Vec::new(),
+            power: Some(true),
+            color: Some("kelvin:2700".to_string()),
+            brightness: Some(0.8),
+            duration: None,
+        };
+        let fake = Fake::default();
+        scene.apply_with(&fake, true).unwrap();
+        assert_eq!(
+            fake.calls.replace(Vec::new()),
+            vec![Call::Set(true, Some(0.8))]
+        );
+        scene.color = None;
+        scene.brightness = None;
+        scene.apply_with(&fake, true).unwrap();
+        assert_eq!(fake.calls.replace(Vec::new()), vec![Call::Power(true)]);
+        scene.power = Some(false);
+        scene.color = Some("red".to_string());
+        scene.apply_with(&fake, true).unwrap();
+        assert_eq!(fake.calls.replace(Vec::new()), vec![Call::Power(false)]);
+        scene.power = None;
+        scene.color = Some("mauve".to_string());
+        match scene.apply_with(&fake, true) {
+            Err(Error::InvalidColor(color)) => assert_eq!(color, "mauve"),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        scene.color = None;
+        scene.brightness = Some(50.0);
+        match scene.apply_with(&fake, true) {
+            Err(Error::InvalidBrightness(brightness)) => assert_eq!(brightness, 50.0),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert!(fake.calls.borrow().is_empty());
+    }
+}
@@ -0,0 +1,562 @@
+//! Time-based automations.
+//!
+//! Schedules fire at a cron-style expression (`cron = "30 7 * * 1-5"`), once at a fixed local time
+//! (`at = "2019-12-24 18:00"`), or at a time of day on certain days of the week
+//! (`time = "07:30"`, `days = ["sat", "sun"]`). Given a location, they can also fire relative to a
+//! solar event (`sun = "sunset-30m"`).
+
+use std::{fmt, str::FromStr, thread};
+
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Weekday};
+
+use crate::{
+    action,
+    action::Action,
+    message::Message,
+    solar::{self, Location, SolarTime},
+};
+
+/// The longest the scheduler sleeps at once, so that changes to the system clock are noticed.
+const MAX_SLEEP: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// A configured automation which fires at certain times.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Schedule {
+    /// A name for the schedule.
+    pub name: Option<String>,
+    /// A cron expression (minute, hour, day of month, month, and day of week).
+    pub cron: Option<String>,
+    /// A single local time at which to fire (e.g. `2019-12-24 18:00`).
+    pub at: Option<String>,
+    /// A local time of day at which to fire (e.g. `07:30`).
+    pub time: Option<String>,
+    /// A time relative to sunrise, sunset, dawn, or dusk (e.g. `sunset-30m`).
+    pub sun: Option<String>,
+    /// The days of the week on which `time` or `sun` applies (every day if omitted).
+    pub days: Option<Vec<String>>,
+    /// What to do when the schedule fires.
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Represents an error in the definition of a schedule.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The cron expression couldn't be parsed.
+    InvalidCron(String),
+    /// A time couldn't be parsed.
+    InvalidTime(String),
+    /// A day of the week couldn't be parsed.
+    InvalidDay(String),
+    /// A solar time couldn't be parsed.
+    Solar(solar::Error),
+    /// A solar time was used without configuring a latitude and longitude.
+    NoLocation,
+    /// None (or more than one) of `cron`, `at`, `time`, and `sun` was given.
+    Trigger,
+    /// The schedule's action is invalid.
+    Action(action::Error),
+}
+
+impl From<action::Error> for Error {
+    fn from(err: action::Error) -> Self {
+        Error::Action(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            InvalidCron(cron) => write!(f, "Invalid cron expression {}", cron),
+            InvalidTime(time) => write!(f, "Invalid time {}", time),
+            InvalidDay(day) => write!(f, "Unrecognized day {}", day),
+            Solar(err) => write!(f, "{}", err),
+            NoLocation => write!(f, "Solar times require a latitude and longitude"),
+            Trigger => write!(f, "Exactly one of cron, at, time, and sun must be given"),
+            Action(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Parses one cron field into a bitset of the values it matches.
+fn field(s: &str, min: u32, max: u32, names: &[&str]) -> Option<u64> {
+    let value = |v: &str| {
+        names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(v))
+            .map(|i| i as u32 + min)
+            .or_else(|| v.parse().ok())
+    };
+    let mut bits = 0;
+    for part in s.split(',') {
+        let mut pieces = part.splitn(2, '/');
+        let range = pieces.next()?;
+        let step = match pieces.next() {
+            Some(step) => Some(step.parse::<usize>().ok().filter(|&n| n > 0)?),
+            None => None,
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else {
+            let mut ends = range.splitn(2, '-');
+            let lo = value(ends.next()?)?;
+            match ends.next() {
+                Some(hi) => (lo, value(hi)?),
+                None if step.is_some() => (lo, max),
+                None => (lo, lo),
+            }
+        };
+        if lo < min || hi > max || lo > hi {
+            return None;
+        }
+        for v in (lo..=hi).step_by(step.unwrap_or(1)) {
+            bits |= 1 << v;
+        }
+    }
+    Some(bits)
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// A parsed cron expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month was unrestricted (`*`).
+    any_day: bool,
+    /// Whether the day of week was unrestricted (`*`).
+    any_weekday: bool,
+}
+
+impl FromStr for Cron {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidCron(s.to_string());
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(invalid());
+        }
+        let parse =
+            |i: usize, min, max, names| field(fields[i], min, max, names).ok_or_else(invalid);
+        let weekdays = parse(4, 0, 7, WEEKDAYS)?;
+        Ok(Self {
+            minutes: parse(0, 0, 59, &[])?,
+            hours: parse(1, 0, 23, &[])?,
+            days: parse(2, 1, 31, &[])?,
+            months: parse(3, 1, 12, MONTHS)?,
+            // Both 0 and 7 mean Sunday.
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+}
+
+impl Cron {
+    fn matches(&self, date: NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        // As in cron, a restricted day of month and day of week match if either does.
+        has(self.months, date.month())
+            && match (self.any_day, self.any_weekday) {
+                (true, true) => true,
+                (true, false) => weekday,
+                (false, true) => day,
+                (false, false) => day || weekday,
+            }
+    }
+    /// The first minute after the given time matched by the expression.
+    pub fn next_after(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date();
+        // Four years covers expressions which only match on leap days.
+        for _ in 0..(366 * 4) {
+            if self.matches(date) {
+                for hour in (0..24).filter(|&h| has(self.hours, h)) {
+                    for minute in (0..60).filter(|&m| has(self.minutes, m)) {
+                        let candidate = date.and_hms_opt(hour, minute, 0)?;
+                        if candidate >= start {
+                            return Some(candidate);
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+}
+
+fn time(s: &str) -> Result<NaiveTime, Error> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+        .map_err(|_| Error::InvalidTime(s.to_string()))
+}
+
+fn date_time(s: &str) -> Result<NaiveDateTime, Error> {
+    [
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+    .ok_or_else(|| Error::InvalidTime(s.to_string()))
+}
+
+/// When a schedule fires.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trigger {
+    /// Whenever a cron expression matches.
+    Cron(Cron),
+    /// Once, at the given local time.
+    At(NaiveDateTime),
+    /// At the given local time of day, on the given days (or every day, if none are given).
+    Time { time: NaiveTime, days: Vec<Weekday> },
+    /// At a time relative to a solar event, on the given days (or every day, if none are given).
+    Sun {
+        time: SolarTime,
+        location: Location,
+        days: Vec<Weekday>,
+    },
+}
+
+impl Trigger {
+    /// Parses a one-off time: a date and time, a time of day (today or tomorrow), or a solar time
+    /// such as `sunset-30m` (which requires a location).
+    pub fn once(s: &str, location: Option<Location>) -> Result<Self, Error> {
+        if let Ok(at) = date_time(s) {
+            return Ok(Trigger::At(at));
+        }
+        if let Ok(time) = time(s) {
+            return Ok(Trigger::Time {
+                time,
+                days: Vec::new(),
+            });
+        }
+        let time = s.parse::<SolarTime>().map_err(Error::Solar)?;
+        let location = location.ok_or(Error::NoLocation)?;
+        Ok(Trigger::Sun {
+            time,
+            location,
+            days: Vec::new(),
+        })
+    }
+    /// The first time after the given time at which the trigger fires.
+    pub fn next_after(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            Trigger::Cron(cron) => cron.next_after(now),
+            Trigger::At(at) => Some(*at).filter(|at| *at > now),
+            Trigger::Time { time, days } => (0..=7)
+                .map(|n| now.date() + Duration::days(n))
+                .filter(|date| days.is_empty() || days.contains(&date.weekday()))
+                .map(|date| date.and_time(*time))
+                .find(|candidate| *candidate > now),
+            // Offsets can push a solar time into a neighboring day, so yesterday is checked too.
+            // Events may also not occur on some days near the poles, so look a little further.
+            Trigger::Sun {
+                time,
+                location,
+                days,
+            } => (-1..=14)
+                .map(|n| now.date() + Duration::days(n))
+                .filter(|date| days.is_empty() || days.contains(&date.weekday()))
+                .filter_map(|date| time.on(date, *location))
+                .find(|candidate| *candidate > now),
+        }
+    }
+}
+
+impl Schedule {
+    fn days(&self) -> Result<Vec<Weekday>, Error> {
+        self.days
+            .iter()
+            .flatten()
+            .map(|d| d.parse().map_err(|_| Error::InvalidDay(d.clone())))
+            .collect()
+    }
+    /// Parses the schedule's trigger.
+    ///
+    /// Solar times (`sun`) require the user's location.
+    pub fn trigger(&self, location: Option<Location>) -> Result<Trigger, Error> {
+        match (&self.cron, &self.at, &self.time, &self.sun) {
+            (Some(cron), None, None, None) => Ok(Trigger::Cron(cron.parse()?)),
+            (None, Some(at), None, None) => Ok(Trigger::At(date_time(at)?)),
+            (None, None, Some(t), None) => Ok(Trigger::Time {
+                time: time(t)?,
+                days: self.days()?,
+            }),
+            (None, None, None, Some(sun)) => Ok(Trigger::Sun {
+                time: sun.parse().map_err(Error::Solar)?,
+                location: location.ok_or(Error::NoLocation)?,
+                days: self.days()?,
+            }),
+            _ => Err(Error::Trigger),
+        }
+    }
+}
+
+/// A source of the current (local) time, which can be replaced for testing.
+pub trait Clock {
+    /// The current local time.
+    fn now(&self) -> NaiveDateTime;
+    /// Waits for the given duration.
+    fn sleep(&self, duration: std::time::Duration);
+}
+
+/// The system clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+    fn sleep(&self, duration: std::time::Duration) {
+        thread::sleep(duration);
+    }
+}
+
+struct Entry {
+    trigger: Trigger,
+    message: Message,
+    next: Option<NaiveDateTime>,
+}
+
+/// Fires the messages of a set of schedules as they come due.
+pub struct Scheduler<C> {
+    clock: C,
+    entries: Vec<Entry>,
+}
+
+impl<C: Clock> Scheduler<C> {
+    /// Creates a scheduler for the given schedules, which fire only after the current time.
+    ///
+    /// The location is used for schedules relative to solar events.
+    pub fn new(
+        schedules: &[Schedule],
+        location: Option<Location>,
+        clock: C,
+    ) -> Result<Self, Error> {
+        let now = clock.now();
+        let entries = schedules
+            .iter()
+            .map(|schedule| -> Result<Entry, Error> {
+                let trigger = schedule.trigger(location)?;
+                Ok(Entry {
+                    message: schedule.action.message()?,
+                    next: trigger.next_after(now),
+                    trigger,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self { clock, entries })
+    }
+    /// The next time at which any schedule fires, if any will.
+    pub fn next(&self) -> Option<NaiveDateTime> {
+        self.entries.iter().filter_map(|entry| entry.next).min()
+    }
+    /// The messages of the schedules which have come due, advancing them to their next times.
+    pub fn due(&mut self) -> Vec<Message> {
+        let now = self.clock.now();
+        let mut messages = Vec::new();
+        for entry in &mut self.entries {
+            if entry.next.is_some_and(|next| next <= now) {
+                messages.push(entry.message.clone());
+                entry.next = entry.trigger.next_after(now);
+            }
+        }
+        messages
+    }
+    /// Dispatches messages as their schedules fire, returning once no schedules remain.
+    pub fn run<F: FnMut(Message)>(mut self, mut dispatch: F) {
+        while let Some(next) = self.next() {
+            let now = self.clock.now();
+            if next > now {
+                let wait = (next - now).to_std().unwrap_or(MAX_SLEEP);
+                self.clock.sleep(wait.min(MAX_SLEEP));
+            }
+            for message in self.due() {
+                dispatch(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct Fake(Cell<NaiveDateTime>);
+
+    impl Clock for Fake {
+        fn now(&self) -> NaiveDateTime {
+            self.0.get()
+        }
+        fn sleep(&self, duration: std::time::Duration) {
+            self.0
+                .set(self.0.get() + Duration::from_std(duration).unwrap());
+        }
+    }
+
+    fn at(s: &str) -> NaiveDateTime {
+        date_time(s).expect("Failed to parse time.")
+    }
+
+    fn schedule(s: &str) -> Schedule {
+        toml::from_str(s).expect("Failed to parse schedule.")
+    }
+
+    #[test]
+    fn cron() {
+        let next = |cron: &str, now| cron.parse::<Cron>().unwrap().next_after(at(now));
+        // 2019-01-01 was a Tuesday.
+        assert_eq!(
+            next("30 7 * * *", "2019-01-01 07:30"),
+            Some(at("2019-01-02 07:30"))
+        );
+        assert_eq!(
+            next("30 7 * * 1-5", "2019-01-04 08:00"),
+            Some(at("2019-01-07 07:30"))
+        );
+        assert_eq!(
+            next("*/15 * * * *", "2019-01-01 07:31"),
+            Some(at("2019-01-01 07:45"))
+        );
+        assert_eq!(
+            next("0 0 1 jan *", "2019-01-01 00:00"),
+            Some(at("2020-01-01 00:00"))
+        );
+        assert_eq!(
+            next("0 12 29 feb *", "2019-01-01 00:00"),
+            Some(at("2020-02-29 12:00"))
+        );
+        assert_eq!(
+            next("0 12 13 * fri", "2019-01-01 00:00"),
+            Some(at("2019-01-04 12:00"))
+        );
+        assert_eq!(
+            next("0 9 * * 7", "2019-01-01 00:00"),
+            next("0 9 * * sun", "2019-01-01 00:00")
+        );
+        assert!("* * * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("* * 0 * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn triggers() {
+        let weekends = schedule("time = \"09:00\"\ndays = [\"sat\", \"Sunday\"]\nscene = \"x\"");
+        let trigger = weekends.trigger(None).unwrap();
+        assert_eq!(
+            trigger.next_after(at("2019-01-01 12:00")),
+            Some(at("2019-01-05 09:00"))
+        );
+        assert_eq!(
+            trigger.next_after(at("2019-01-05 09:00")),
+            Some(at("2019-01-06 09:00"))
+        );
+        let daily = schedule("time = \"21:30\"\nscene = \"x\"")
+            .trigger(None)
+            .unwrap();
+        assert_eq!(
+            daily.next_after(at("2019-01-01 21:29")),
+            Some(at("2019-01-01 21:30"))
+        );
+        let once = schedule("at = \"2019-01-01T18:00\"\nscene = \"x\"")
+            .trigger(None)
+            .unwrap();
+        assert_eq!(
+            once.next_after(at("2019-01-01 12:00")),
+            Some(at("2019-01-01 18:00"))
+        );
+        assert_eq!(once.next_after(at("2019-01-01 18:00")), None);
+        assert_eq!(
+            schedule("scene = \"x\"").trigger(None).err(),
+            Some(Error::Trigger)
+        );
+        assert_eq!(
+            schedule("time = \"7\"\nscene = \"x\"").trigger(None).err(),
+            Some(Error::InvalidTime("7".to_string()))
+        );
+        assert_eq!(
+            schedule("time = \"07:00\"\ndays = [\"someday\"]\nscene = \"x\"")
+                .trigger(None)
+                .err(),
+            Some(Error::InvalidDay("someday".to_string()))
+        );
+        let sunset = schedule("sun = \"sunset-30m\"\nscene = \"x\"");
+        assert_eq!(sunset.trigger(None).err(), Some(Error::NoLocation));
+        let location = Location {
+            latitude: 40.7128,
+            longitude: -74.006,
+        };
+        let now = at("2019-06-21 12:00");
+        let next = sunset.trigger(Some(location)).unwrap().next_after(now);
+        assert!(next.is_some_and(|next| next > now && next - now < Duration::days(1)));
+    }
+
+    #[test]
+    fn once() {
+        let location = Some(Location {
+            latitude: 40.7128,
+            longitude: -74.006,
+        });
+        assert_eq!(
+            Trigger::once("2019-01-01 18:00", None),
+            Ok(Trigger::At(at("2019-01-01 18:00")))
+        );
+        match Trigger::once("18:00", None) {
+            Ok(Trigger::Time { days, .. }) => assert!(days.is_empty()),
+            t => panic!("Unexpected trigger {:?}", t),
+        }
+        match Trigger::once("dusk+5m", location) {
+            Ok(Trigger::Sun { time, .. }) => assert_eq!(time.offset, Duration::minutes(5)),
+            t => panic!("Unexpected trigger {:?}", t),
+        }
+        assert_eq!(Trigger::once("sunset", None), Err(Error::NoLocation));
+        assert!(Trigger::once("later", location).is_err());
+    }
+
+    #[test]
+    fn scheduler() {
+        let schedules = vec![
+            schedule("at = \"2019-01-01 18:00\"\ndevice = \"porch\"\npower = true"),
+            schedule("at = \"2019-01-01 23:00\"\ndevice = \"porch\"\npower = false"),
+            schedule("at = \"2019-01-01 06:00\"\nscene = \"missed\""),
+        ];
+        let clock = Fake(Cell::new(at("2019-01-01 12:00")));
+        let mut scheduler = Scheduler::new(&schedules, None, clock).unwrap();
+        assert_eq!(scheduler.next(), Some(at("2019-01-01 18:00")));
+        assert!(scheduler.due().is_empty());
+        let mut fired = Vec::new();
+        scheduler.run(|message| {
+            if let Message::Power { power, .. } = message {
+                fired.push(power);
+            }
+        });
+        assert_eq!(fired, vec![true, false]);
+        let invalid = vec![schedule("time = \"07:00\"\ndevice = \"porch\"")];
+        let clock = Fake(Cell::new(at("2019-01-01 12:00")));
+        assert!(Scheduler::new(&invalid, None, clock).is_err());
+    }
+}
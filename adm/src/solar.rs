@@ -0,0 +1,223 @@
+//! Sunrise, sunset, and civil twilight times, computed offline.
+//!
+//! Times are computed with the NOAA solar equations, which are accurate to within a few minutes away
+//! from the poles.
+
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use crate::parse;
+
+/// The solar elevation (in degrees) at sunrise and sunset, accounting for refraction and the size
+/// of the solar disc.
+const HORIZON: f64 = -0.833;
+/// The solar elevation (in degrees) at the start of civil dawn and the end of civil dusk.
+const CIVIL_TWILIGHT: f64 = -6.0;
+/// The Julian date of 2000-01-01 12:00 UTC.
+const J2000: f64 = 2_451_545.0;
+/// The Julian date of the Unix epoch.
+const UNIX_EPOCH: f64 = 2_440_587.5;
+
+/// A position on Earth.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Location {
+    /// The latitude, in degrees north.
+    pub latitude: f64,
+    /// The longitude, in degrees east.
+    pub longitude: f64,
+}
+
+/// A daily solar event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// The start of civil twilight in the morning.
+    Dawn,
+    /// The moment the sun rises above the horizon.
+    Sunrise,
+    /// The moment the sun sets below the horizon.
+    Sunset,
+    /// The end of civil twilight in the evening.
+    Dusk,
+}
+
+impl FromStr for Event {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dawn" => Ok(Event::Dawn),
+            "sunrise" => Ok(Event::Sunrise),
+            "sunset" => Ok(Event::Sunset),
+            "dusk" => Ok(Event::Dusk),
+            _ => Err(Error(s.to_string())),
+        }
+    }
+}
+
+/// Represents an unparseable solar event or offset.
+#[derive(Debug, PartialEq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unrecognized solar time {} (expected e.g. sunset or sunrise+30m)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn sin(degrees: f64) -> f64 {
+    degrees.to_radians().sin()
+}
+
+fn cos(degrees: f64) -> f64 {
+    degrees.to_radians().cos()
+}
+
+/// Computes the time of the given event on the given (local) date.
+///
+/// Returns `None` if the event doesn't occur that day (e.g. during polar day or night).
+pub fn time(event: Event, date: NaiveDate, location: Location) -> Option<DateTime<Utc>> {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+    let days = date.signed_duration_since(epoch).num_days() as f64 + 0.0008;
+    // Mean solar noon.
+    let noon = days - location.longitude / 360.0;
+    let anomaly = (357.5291 + 0.985_600_28 * noon).rem_euclid(360.0);
+    let center = 1.9148 * sin(anomaly) + 0.02 * sin(2.0 * anomaly) + 0.0003 * sin(3.0 * anomaly);
+    let ecliptic = (anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let transit = J2000 + noon + 0.0053 * sin(anomaly) - 0.0069 * sin(2.0 * ecliptic);
+    let declination = (sin(ecliptic) * sin(23.4397)).asin().to_degrees();
+    let elevation = match event {
+        Event::Sunrise | Event::Sunset => HORIZON,
+        Event::Dawn | Event::Dusk => CIVIL_TWILIGHT,
+    };
+    let cos_hour_angle = (sin(elevation) - sin(location.latitude) * sin(declination))
+        / (cos(location.latitude) * cos(declination));
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees() / 360.0;
+    let julian = match event {
+        Event::Dawn | Event::Sunrise => transit - hour_angle,
+        Event::Sunset | Event::Dusk => transit + hour_angle,
+    };
+    let seconds = ((julian - UNIX_EPOCH) * 86400.0).round() as i64;
+    Utc.timestamp_opt(seconds, 0).single()
+}
+
+/// A time relative to a solar event, such as `sunset-30m` or `sunrise+1h`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SolarTime {
+    /// The event the time is relative to.
+    pub event: Event,
+    /// How long after (or, if negative, before) the event the time is.
+    pub offset: Duration,
+}
+
+impl FromStr for SolarTime {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (event, offset) = match s.find(['+', '-']) {
+            Some(i) => s.split_at(i),
+            None => (s, ""),
+        };
+        let invalid = || Error(s.to_string());
+        let offset = match offset.chars().next() {
+            None => Duration::zero(),
+            Some(sign) => {
+                let duration = parse::duration(&offset[1..]).ok_or_else(invalid)?;
+                let duration = Duration::from_std(duration).map_err(|_| invalid())?;
+                if sign == '-' {
+                    -duration
+                } else {
+                    duration
+                }
+            }
+        };
+        Ok(Self {
+            event: event.trim().parse().map_err(|_| invalid())?,
+            offset,
+        })
+    }
+}
+
+impl SolarTime {
+    /// The local time this refers to on the given (local) date, if the event occurs that day.
+    pub fn on(&self, date: NaiveDate, location: Location) -> Option<NaiveDateTime> {
+        let time = time(self.event, date, location)?;
+        Some(Local.from_utc_datetime(&time.naive_utc()).naive_local() + self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEW_YORK: Location = Location {
+        latitude: 40.7128,
+        longitude: -74.006,
+    };
+
+    fn assert_near(actual: Option<DateTime<Utc>>, expected: &str) {
+        let expected = DateTime::parse_from_rfc3339(expected).unwrap();
+        let error = actual
+            .expect("No event found.")
+            .signed_duration_since(expected);
+        assert!(error.num_seconds().abs() <= 180, "Off by {}", error);
+    }
+
+    #[test]
+    fn times() {
+        let solstice = NaiveDate::from_ymd_opt(2019, 6, 21).unwrap();
+        assert_near(
+            time(Event::Sunrise, solstice, NEW_YORK),
+            "2019-06-21T05:25:00-04:00",
+        );
+        assert_near(
+            time(Event::Sunset, solstice, NEW_YORK),
+            "2019-06-21T20:31:00-04:00",
+        );
+        assert_near(
+            time(Event::Dawn, solstice, NEW_YORK),
+            "2019-06-21T04:53:00-04:00",
+        );
+        assert_near(
+            time(Event::Dusk, solstice, NEW_YORK),
+            "2019-06-21T21:03:00-04:00",
+        );
+        let tromso = Location {
+            latitude: 69.6492,
+            longitude: 18.9553,
+        };
+        assert!(time(Event::Sunset, solstice, tromso).is_none());
+    }
+
+    #[test]
+    fn solar_times() {
+        let parse = |s: &str| s.parse::<SolarTime>();
+        assert_eq!(
+            parse("sunset-30m"),
+            Ok(SolarTime {
+                event: Event::Sunset,
+                offset: Duration::minutes(-30),
+            })
+        );
+        assert_eq!(
+            parse("Sunrise + 1h"),
+            Ok(SolarTime {
+                event: Event::Sunrise,
+                offset: Duration::hours(1),
+            })
+        );
+        assert_eq!(parse("dusk").map(|t| t.offset), Ok(Duration::zero()));
+        assert!(parse("noon").is_err());
+        assert!(parse("sunset-").is_err());
+        assert!(parse("sunset-soon").is_err());
+        assert!(parse("sunset-99999999999999999999h").is_err());
+    }
+}
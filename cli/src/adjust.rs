@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use crate::error::AdjustError;
+use adm::{device::Delta, message::Message, parse};
+
+/// Splits a leading sign off a value, returning whether it was negative.
+fn signed(s: &str) -> (bool, &str) {
+    let s = s.trim();
+    match s.chars().next() {
+        Some('-') => (true, &s[1..]),
+        Some('+') => (false, &s[1..]),
+        _ => (false, s),
+    }
+}
+
+/// Parses a signed percentage (e.g. `+10%` or `-25`) into a fraction.
+fn percentage(s: &str) -> Option<f32> {
+    let (negative, magnitude) = signed(s);
+    let fraction = parse::brightness(magnitude)?;
+    Some(if negative { -fraction } else { fraction })
+}
+
+/// Parses a signed number, ignoring the given unit suffix (e.g. `-500K` or `+30°`).
+fn number<T: std::str::FromStr + std::ops::Neg<Output = T>>(s: &str, unit: char) -> Option<T> {
+    let (negative, magnitude) = signed(s);
+    let magnitude = magnitude.strip_suffix(unit).unwrap_or(magnitude).trim();
+    if magnitude.starts_with(['+', '-']) {
+        return None;
+    }
+    let value = magnitude.parse::<T>().ok()?;
+    Some(if negative { -value } else { value })
+}
+
+fn duration(duration: Option<String>) -> Result<Option<Duration>, AdjustError> {
+    match duration {
+        Some(d) => Ok(Some(
+            parse::duration(&d).ok_or(AdjustError::InvalidDuration(d))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+pub fn adjust(
+    device: String,
+    brightness: Option<String>,
+    hue: Option<String>,
+    saturation: Option<String>,
+    kelvin: Option<String>,
+    fade: Option<String>,
+) -> Result<Option<Message>, AdjustError> {
+    let delta = Delta {
+        brightness: match brightness {
+            Some(b) => Some(percentage(&b).ok_or(AdjustError::InvalidBrightness(b))?),
+            None => None,
+        },
+        hue: match hue {
+            Some(h) => Some(number(&h, '°').ok_or(AdjustError::InvalidHue(h))?),
+            None => None,
+        },
+        saturation: match saturation {
+            Some(s) => Some(percentage(&s).ok_or(AdjustError::InvalidSaturation(s))?),
+            None => None,
+        },
+        kelvin: match kelvin {
+            Some(k) => Some(number(&k, 'K').ok_or(AdjustError::InvalidKelvin(k))?),
+            None => None,
+        },
+    };
+    if delta.is_empty() {
+        return Err(AdjustError::Empty);
+    }
+    Ok(Some(Message::Adjust {
+        device,
+        delta,
+        duration: duration(fade)?,
+    }))
+}
+
+/// Brightens (or, if `dim` is set, dims) a device by the given percentage.
+pub fn brighten(
+    device: String,
+    amount: String,
+    dim: bool,
+    fade: Option<String>,
+) -> Result<Option<Message>, AdjustError> {
+    let brightness = parse::brightness(&amount).ok_or(AdjustError::InvalidBrightness(amount))?;
+    Ok(Some(Message::Adjust {
+        device,
+        delta: Delta {
+            brightness: Some(if dim { -brightness } else { brightness }),
+            ..Delta::default()
+        },
+        duration: duration(fade)?,
+    }))
+}
@@ -0,0 +1,30 @@
+//! Waiting to run commands later.
+use std::time::Duration;
+
+use adm::{
+    schedule::{Clock, SystemClock, Trigger},
+    solar::Location,
+};
+
+use crate::error::AtError;
+
+/// The longest to sleep at once, so that changes to the system clock are noticed.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Blocks until the given time, using the location for times relative to solar events.
+pub fn wait(time: &str, location: Option<Location>) -> Result<(), AtError> {
+    let clock = SystemClock;
+    let trigger = Trigger::once(time, location)?;
+    let target = trigger
+        .next_after(clock.now())
+        .ok_or_else(|| AtError::Never(time.to_string()))?;
+    eprintln!("Waiting until {}", target.format("%Y-%m-%d %H:%M:%S"));
+    loop {
+        let now = clock.now();
+        if now >= target {
+            return Ok(());
+        }
+        let remaining = (target - now).to_std().unwrap_or_default();
+        clock.sleep(remaining.min(MAX_SLEEP));
+    }
+}
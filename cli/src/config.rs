@@ -1,7 +1,14 @@
-use adm::config::CONFIG;
+use std::fs::read_to_string;
+
+use adm::{
+    config::{self, Config, CONFIG},
+    device::{Device, Type},
+    lan,
+    lifxi::http::Selector,
+};
 use structopt::StructOpt;
 
-use crate::error::ConfigError;
+use crate::{error::ConfigError, prompt};
 
 #[derive(Debug, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
@@ -12,6 +19,12 @@ pub enum Set {
         ///
         /// If left unspecified, the user will be prompted for the secret.
         value: Option<String>,
+        /// Read the secret from standard input instead of prompting for it.
+        #[structopt(long)]
+        stdin: bool,
+        /// Check that the secret is accepted by LIFX before saving it.
+        #[structopt(long)]
+        verify: bool,
     },
 }
 
@@ -19,9 +32,38 @@ pub enum Set {
 #[structopt(rename_all = "kebab-case")]
 pub enum ConfigCommand {
     /// Add a device to the list of configured devices.
-    Add,
+    ///
+    /// Any options left unspecified will be prompted for.
+    Add {
+        /// The device type (lifx or lifx-lan).
+        #[structopt(long = "type")]
+        kind: Option<String>,
+        /// The device name.
+        #[structopt(long)]
+        name: Option<String>,
+        /// The LIFX selector matching the device (e.g. label:Lamp), for lifx devices.
+        #[structopt(long)]
+        selector: Option<String>,
+        /// The IP address of the device, for lifx-lan devices.
+        #[structopt(long)]
+        address: Option<String>,
+        /// The MAC address of the device, for lifx-lan devices.
+        #[structopt(long)]
+        mac: Option<String>,
+        /// An alternative name for the device (may be repeated).
+        #[structopt(long, raw(number_of_values = "1"))]
+        alias: Vec<String>,
+    },
     /// Remove a device from the list of configured devices.
     Remove { device: String },
+    /// Check the config file for problems, such as duplicate names or invalid selectors.
+    ///
+    /// Exits with an error if the config file can't be used as written.
+    Check {
+        /// Print the report as JSON.
+        #[structopt(long)]
+        json: bool,
+    },
     /// Set a root-level configuration option.
     Set {
         #[structopt(subcommand)]
@@ -29,9 +71,129 @@ pub enum ConfigCommand {
     },
 }
 
+fn device_type(
+    kind: Option<String>,
+    selector: Option<String>,
+    address: Option<String>,
+    mac: Option<String>,
+) -> Result<Type, ConfigError> {
+    let kind = prompt::or_prompt(kind, "Device type (lifx, lifx-lan)")?
+        .ok_or(ConfigError::MissingValue("type"))?;
+    match kind.as_str() {
+        "lifx" => {
+            let selector = prompt::or_prompt(selector, "Selector (e.g. label:Lamp)")?
+                .ok_or(ConfigError::MissingValue("selector"))?;
+            match selector.parse::<Selector>() {
+                Ok(selector) => Ok(Type::LifxBulb { selector }),
+                Err(_) => Err(ConfigError::InvalidSelector(selector)),
+            }
+        }
+        "lifx-lan" => {
+            let (address, mac) = if address.is_none() && mac.is_none() {
+                (
+                    prompt::optional("IP address (optional if MAC address given)")?,
+                    prompt::optional("MAC address (optional if IP address given)")?,
+                )
+            } else {
+                (address, mac)
+            };
+            if address.is_none() && mac.is_none() {
+                return Err(ConfigError::MissingValue("address or mac"));
+            }
+            if let Some(address) = &address {
+                if lan::parse_address(address).is_none() {
+                    return Err(ConfigError::InvalidAddress(address.clone()));
+                }
+            }
+            if let Some(mac) = &mac {
+                if lan::parse_mac(mac).is_none() {
+                    return Err(ConfigError::InvalidAddress(mac.clone()));
+                }
+            }
+            Ok(Type::LifxLan { address, mac })
+        }
+        _ => Err(ConfigError::UnknownType(kind)),
+    }
+}
+
+fn add(
+    kind: Option<String>,
+    name: Option<String>,
+    selector: Option<String>,
+    address: Option<String>,
+    mac: Option<String>,
+    mut alias: Vec<String>,
+) -> Result<(), ConfigError> {
+    let interactive = name.is_none();
+    let r#type = device_type(kind, selector, address, mac)?;
+    let name = prompt::or_prompt(name, "Name")?.ok_or(ConfigError::MissingValue("name"))?;
+    if interactive && alias.is_empty() {
+        if let Some(aliases) = prompt::optional("Aliases (comma-separated, optional)")? {
+            alias = aliases
+                .split(',')
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect();
+        }
+    }
+    let mut names = vec![&name];
+    names.extend(alias.iter());
+    for (index, candidate) in names.iter().enumerate() {
+        let repeated = names[..index]
+            .iter()
+            .any(|n| n.eq_ignore_ascii_case(candidate));
+        if repeated || CONFIG.find(candidate).is_some() {
+            return Err(ConfigError::Duplicate(candidate.to_string()));
+        }
+    }
+    let mut config = CONFIG.clone();
+    config.devices.push(Device {
+        r#type,
+        name,
+        alternatives: if alias.is_empty() { None } else { Some(alias) },
+    });
+    config.write()?;
+    Ok(())
+}
+
+fn check(json: bool) -> Result<(), ConfigError> {
+    let path = Config::path()?;
+    let report = adm::check::check(&read_to_string(&path)?);
+    if json {
+        let output = serde_json::json!({
+            "path": path,
+            "valid": report.is_valid(),
+            "diagnostics": report.diagnostics,
+        });
+        println!("{:#}", output);
+    } else if report.diagnostics.is_empty() {
+        println!("{}: no problems found", path.display());
+    } else {
+        for diagnostic in &report.diagnostics {
+            match (diagnostic.line, diagnostic.column) {
+                (Some(line), Some(column)) => {
+                    println!("{}:{}:{}: {}", path.display(), line, column, diagnostic)
+                }
+                _ => println!("{}: {}", path.display(), diagnostic),
+            }
+        }
+    }
+    match report.errors() {
+        0 => Ok(()),
+        errors => Err(ConfigError::Invalid(errors)),
+    }
+}
+
 pub fn config(command: ConfigCommand) -> Result<(), ConfigError> {
     match command {
-        ConfigCommand::Add => unimplemented!(),
+        ConfigCommand::Add {
+            kind,
+            name,
+            selector,
+            address,
+            mac,
+            alias,
+        } => add(kind, name, selector, address, mac, alias),
         ConfigCommand::Remove { device } => {
             if let Some(device) = CONFIG.find(&device) {
                 let mut config = CONFIG.clone();
@@ -42,13 +204,24 @@ pub fn config(command: ConfigCommand) -> Result<(), ConfigError> {
                 Err(ConfigError::DeviceNotFound(device))
             }
         }
+        ConfigCommand::Check { json } => check(json),
         ConfigCommand::Set { key } => match key {
-            Set::LifxSecret { value } => {
-                if value.is_none() {
-                    return unimplemented!();
+            Set::LifxSecret {
+                value,
+                stdin,
+                verify,
+            } => {
+                let secret = match value {
+                    Some(value) => Some(value),
+                    None if stdin => prompt::line()?,
+                    None => prompt::secret("LIFX secret")?,
+                };
+                let secret = secret.ok_or(ConfigError::MissingValue("secret"))?;
+                if verify && !config::verify_lifx_secret(&secret)? {
+                    return Err(ConfigError::RejectedSecret);
                 }
                 let mut config = CONFIG.clone();
-                config.lifx_secret = value;
+                config.lifx_secret = Some(secret);
                 config.write()?;
                 Ok(())
             }
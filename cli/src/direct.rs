@@ -0,0 +1,36 @@
+//! Controlling devices in-process, without going through the MQTT broker.
+use adm::{
+    config::{CONFIG, CONTEXT},
+    message::Message,
+};
+
+use crate::error::ExecuteError;
+
+pub fn execute(message: Message) -> Result<(), ExecuteError> {
+    if let Message::Scene { name } = &message {
+        let scene = CONFIG
+            .scene(name)
+            .ok_or_else(|| ExecuteError::SceneNotFound(name.clone()))?;
+        let report = scene.apply(&CONTEXT, false);
+        if !report.is_success() {
+            return Err(ExecuteError::Scene(report.failures));
+        }
+        return Ok(());
+    }
+    let name = message.device().unwrap_or_default().to_string();
+    let devices = CONFIG.resolve(&name);
+    if devices.is_empty() {
+        return Err(ExecuteError::DeviceNotFound(name));
+    }
+    let failures: Vec<_> = devices
+        .into_iter()
+        .filter_map(|device| {
+            let result = message.clone().apply(&CONTEXT, device, false);
+            result.err().map(|err| (device.name.clone(), err))
+        })
+        .collect();
+    if !failures.is_empty() {
+        return Err(ExecuteError::Devices(failures));
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use crate::error::EffectError;
+use adm::{
+    effect::{Effect, Waveform},
+    lifxi::http::Color,
+    message::Message,
+    parse,
+};
+
+fn color(color: String) -> Result<Color, EffectError> {
+    parse::lifx_color(&color).ok_or(EffectError::InvalidColor(color))
+}
+
+fn duration(duration: Option<String>) -> Result<Option<Duration>, EffectError> {
+    match duration {
+        Some(d) => Ok(Some(
+            parse::duration(&d).ok_or(EffectError::InvalidDuration(d))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+pub struct Options {
+    pub color: Option<String>,
+    pub from: Option<String>,
+    pub period: Option<String>,
+    pub cycles: Option<u16>,
+    pub persist: bool,
+    pub peak: Option<f32>,
+    pub palette: Vec<String>,
+    pub duration: Option<String>,
+}
+
+fn waveform(options: Options) -> Result<Waveform, EffectError> {
+    if let Some(peak) = options.peak.filter(|p| !(0.0..=1.0).contains(p)) {
+        return Err(EffectError::InvalidPeak(peak));
+    }
+    Ok(Waveform {
+        from: options.from.map(color).transpose()?,
+        period: duration(options.period)?,
+        cycles: options.cycles,
+        persist: options.persist,
+        peak: options.peak,
+        ..Waveform::new(color(options.color.ok_or(EffectError::NoColor)?)?)
+    })
+}
+
+pub fn effect(
+    device: String,
+    effect: String,
+    options: Options,
+) -> Result<Option<Message>, EffectError> {
+    let effect = match effect.to_ascii_lowercase().as_str() {
+        "breathe" => Effect::Breathe(waveform(options)?),
+        "pulse" => Effect::Pulse(waveform(options)?),
+        "morph" => {
+            if options.palette.len() > 16 {
+                return Err(EffectError::PaletteTooLarge(options.palette.len()));
+            }
+            Effect::Morph {
+                palette: options
+                    .palette
+                    .into_iter()
+                    .map(color)
+                    .collect::<Result<_, _>>()?,
+                period: duration(options.period)?,
+                duration: duration(options.duration)?,
+            }
+        }
+        "flame" => Effect::Flame {
+            period: duration(options.period)?,
+            duration: duration(options.duration)?,
+        },
+        _ => return Err(EffectError::UnknownEffect(effect)),
+    };
+    Ok(Some(Message::Effect { device, effect }))
+}
@@ -1,10 +1,17 @@
 //! Error handling.
+#[cfg(feature = "mqtt")]
+use std::time::Duration;
 use std::{error::Error as ErrorT, fmt, io};
 
-// TODO: Add wrapper type around this so we can expose a generic client error instead of lifxi's.
-use adm::lifxi::http::Error as LifxiClientError;
+#[cfg(feature = "mqtt")]
+use adm::message::MqttError;
+use adm::{
+    backend::Error as BackendError, config::Error as ConfigFileError, scene::Error as SceneError,
+    schedule::Error as ScheduleError,
+};
 
 /// Represents an error encountered while sending an MQTT message.
+#[cfg(feature = "mqtt")]
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
 pub enum SendError {
@@ -12,29 +19,65 @@ pub enum SendError {
     Serialize(serde_json::Error),
     /// An error was encountered in the MQTT pub/sub flow.
     Client(rumqtt::error::ClientError),
+    /// The client couldn't connect to the broker.
+    Connect(rumqtt::error::ConnectError),
+    /// The timeout couldn't be parsed.
+    InvalidTimeout(String),
+    /// The daemon didn't acknowledge the command in time.
+    Timeout(Duration),
+    /// The connection to the broker was lost while waiting for the daemon's reply.
+    Disconnected,
+    /// The daemon couldn't carry out the command.
+    Failed(Vec<MqttError>),
 }
 
+#[cfg(feature = "mqtt")]
 impl From<serde_json::Error> for SendError {
     fn from(err: serde_json::Error) -> Self {
         SendError::Serialize(err)
     }
 }
 
+#[cfg(feature = "mqtt")]
 impl From<rumqtt::error::ClientError> for SendError {
     fn from(err: rumqtt::error::ClientError) -> Self {
         SendError::Client(err)
     }
 }
 
+#[cfg(feature = "mqtt")]
+impl From<rumqtt::error::ConnectError> for SendError {
+    fn from(err: rumqtt::error::ConnectError) -> Self {
+        SendError::Connect(err)
+    }
+}
+
+#[cfg(feature = "mqtt")]
 impl fmt::Display for SendError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             SendError::Serialize(err) => write!(f, "Serialization error: {}", err),
             SendError::Client(err) => write!(f, "MQTT error: {}", err),
+            SendError::Connect(err) => write!(f, "Couldn't connect to the broker: {}", err),
+            SendError::InvalidTimeout(timeout) => write!(f, "Unrecognized timeout {}", timeout),
+            SendError::Timeout(timeout) => write!(
+                f,
+                "No reply from the daemon within {:?} (is adm-mqtt-client running?)",
+                timeout
+            ),
+            SendError::Disconnected => write!(f, "Lost the connection to the broker"),
+            SendError::Failed(failures) => {
+                write!(f, "The command failed:")?;
+                for failure in failures {
+                    write!(f, "\n  {}", failure)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
+#[cfg(feature = "mqtt")]
 impl ErrorT for SendError {}
 
 /// Represents an error encountered while using the `turn` subcommand.
@@ -45,13 +88,15 @@ pub enum TurnError {
     /// To turn *on* a device, use a state of `on` or `1`; to turn *off* a device, use a state of
     /// `off` or `0`.
     UnrecognizedState(String),
-    /// The input was parsed correctly, but the client encountered an error.
-    LifxiClient(LifxiClientError),
+    /// The duration couldn't be parsed.
+    InvalidDuration(String),
+    /// The input was parsed correctly, but the device backend encountered an error.
+    Backend(BackendError),
 }
 
-impl From<LifxiClientError> for TurnError {
-    fn from(err: LifxiClientError) -> Self {
-        TurnError::LifxiClient(err)
+impl From<BackendError> for TurnError {
+    fn from(err: BackendError) -> Self {
+        TurnError::Backend(err)
     }
 }
 
@@ -60,20 +105,229 @@ impl fmt::Display for TurnError {
         use self::TurnError::*;
         match self {
             UnrecognizedState(state) => write!(f, "Unrecognized target state {}", state),
-            LifxiClient(err) => write!(f, "lifxi error: {}", err),
+            InvalidDuration(duration) => write!(f, "Unrecognized duration {}", duration),
+            Backend(err) => write!(f, "{}", err),
         }
     }
 }
 
 impl ErrorT for TurnError {}
 
+/// Represents an error encountered while using the `set` subcommand.
+#[derive(Debug)]
+pub enum SetError {
+    /// The color couldn't be parsed.
+    InvalidColor(String),
+    /// The brightness couldn't be parsed, or was out of range.
+    InvalidBrightness(String),
+    /// The duration couldn't be parsed.
+    InvalidDuration(String),
+    /// Neither a color nor a brightness was given.
+    Empty,
+}
+
+impl fmt::Display for SetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SetError::*;
+        match self {
+            InvalidColor(color) => write!(f, "Unrecognized color {}", color),
+            InvalidBrightness(brightness) => write!(
+                f,
+                "Unrecognized brightness {} (expected a percentage)",
+                brightness
+            ),
+            InvalidDuration(duration) => write!(f, "Unrecognized duration {}", duration),
+            Empty => write!(f, "Nothing to set (use --color and/or --brightness)"),
+        }
+    }
+}
+
+impl ErrorT for SetError {}
+
+/// Represents an error encountered while using the `adjust`, `brighten`, or `dim` subcommands.
+#[derive(Debug)]
+pub enum AdjustError {
+    /// The brightness change couldn't be parsed, or was out of range.
+    InvalidBrightness(String),
+    /// The hue change couldn't be parsed.
+    InvalidHue(String),
+    /// The saturation change couldn't be parsed, or was out of range.
+    InvalidSaturation(String),
+    /// The color temperature change couldn't be parsed.
+    InvalidKelvin(String),
+    /// The duration couldn't be parsed.
+    InvalidDuration(String),
+    /// No changes were given.
+    Empty,
+}
+
+impl fmt::Display for AdjustError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AdjustError::*;
+        match self {
+            InvalidBrightness(brightness) => write!(
+                f,
+                "Unrecognized brightness change {} (expected a percentage, e.g. +10%)",
+                brightness
+            ),
+            InvalidHue(hue) => write!(f, "Unrecognized hue change {} (expected degrees)", hue),
+            InvalidSaturation(saturation) => write!(
+                f,
+                "Unrecognized saturation change {} (expected a percentage)",
+                saturation
+            ),
+            InvalidKelvin(kelvin) => write!(f, "Unrecognized color temperature change {}", kelvin),
+            InvalidDuration(duration) => write!(f, "Unrecognized duration {}", duration),
+            Empty => write!(
+                f,
+                "Nothing to adjust (use --brightness, --hue, --saturation, and/or --kelvin)"
+            ),
+        }
+    }
+}
+
+impl ErrorT for AdjustError {}
+
+/// Represents an error encountered while using the `effect` subcommand.
+#[derive(Debug)]
+pub enum EffectError {
+    /// The effect isn't one of breathe, pulse, morph, or flame.
+    UnknownEffect(String),
+    /// A breathe or pulse effect was requested without a color.
+    NoColor,
+    /// A color couldn't be parsed.
+    InvalidColor(String),
+    /// A duration couldn't be parsed.
+    InvalidDuration(String),
+    /// The peak was outside `[0, 1]`.
+    InvalidPeak(f32),
+    /// The palette had more than 16 colors.
+    PaletteTooLarge(usize),
+}
+
+impl fmt::Display for EffectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::EffectError::*;
+        match self {
+            UnknownEffect(effect) => write!(
+                f,
+                "Unknown effect {} (expected breathe, pulse, morph, or flame)",
+                effect
+            ),
+            NoColor => write!(f, "No color given (use --color)"),
+            InvalidColor(color) => write!(f, "Unrecognized color {}", color),
+            InvalidDuration(duration) => write!(f, "Unrecognized duration {}", duration),
+            InvalidPeak(peak) => write!(f, "Invalid peak {} (expected 0 to 1)", peak),
+            PaletteTooLarge(len) => write!(f, "Palettes can have at most 16 colors, not {}", len),
+        }
+    }
+}
+
+impl ErrorT for EffectError {}
+
+/// Represents an error encountered while using the `at` subcommand.
+#[derive(Debug)]
+pub enum AtError {
+    /// The time couldn't be parsed.
+    InvalidTime(ScheduleError),
+    /// The time will never come (e.g. it's in the past, or the sun won't set for a while).
+    Never(String),
+    /// An option which only applies to the whole invocation was given in the deferred command.
+    LateOption(&'static str),
+}
+
+impl From<ScheduleError> for AtError {
+    fn from(err: ScheduleError) -> Self {
+        AtError::InvalidTime(err)
+    }
+}
+
+impl fmt::Display for AtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AtError::*;
+        match self {
+            InvalidTime(err) => write!(f, "{}", err),
+            Never(time) => write!(f, "{} won't happen any time soon", time),
+            LateOption(option) => write!(f, "{} must be given before `at`", option),
+        }
+    }
+}
+
+impl ErrorT for AtError {}
+
+/// Represents an error encountered while carrying out a command directly.
+#[derive(Debug)]
+pub enum ExecuteError {
+    /// No devices matched the given specifier.
+    DeviceNotFound(String),
+    /// No scene with the given name is configured.
+    SceneNotFound(String),
+    /// The device backend encountered an error.
+    Backend(BackendError),
+    /// A scene couldn't be applied to some of its devices.
+    Scene(Vec<(String, SceneError)>),
+    /// The command couldn't be carried out on some of the devices it addressed.
+    Devices(Vec<(String, BackendError)>),
+}
+
+impl From<BackendError> for ExecuteError {
+    fn from(err: BackendError) -> Self {
+        ExecuteError::Backend(err)
+    }
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ExecuteError::*;
+        match self {
+            DeviceNotFound(device) => write!(f, "No devices found matching specifier {}", device),
+            SceneNotFound(scene) => write!(f, "No scene named {}", scene),
+            Backend(err) => write!(f, "{}", err),
+            Scene(failures) => {
+                write!(f, "The scene couldn't be applied to every device:")?;
+                for (device, err) in failures {
+                    write!(f, "\n  {}: {}", device, err)?;
+                }
+                Ok(())
+            }
+            Devices(failures) => {
+                write!(f, "The command couldn't be carried out on every device:")?;
+                for (device, err) in failures {
+                    write!(f, "\n  {}: {}", device, err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ErrorT for ExecuteError {}
+
 /// Represents an error encountered while using the `config` subcommand.
 #[derive(Debug)]
 pub enum ConfigError {
     /// No devices matched the given specifier.
     DeviceNotFound(String),
+    /// A required value was neither given nor entered at the prompt.
+    MissingValue(&'static str),
+    /// The device type isn't one `adm` knows about.
+    UnknownType(String),
+    /// The LIFX selector couldn't be parsed.
+    InvalidSelector(String),
+    /// The IP or MAC address couldn't be parsed.
+    InvalidAddress(String),
+    /// The name or alias is already used by another device.
+    Duplicate(String),
+    /// LIFX didn't accept the given secret.
+    RejectedSecret,
+    /// The secret couldn't be checked.
+    Verify(BackendError),
     /// An I/O error occured while saving the config.
     Io(io::Error),
+    /// The config file couldn't be located.
+    Location(ConfigFileError),
+    /// The config file has the given number of errors.
+    Invalid(usize),
 }
 
 impl From<io::Error> for ConfigError {
@@ -82,12 +336,37 @@ impl From<io::Error> for ConfigError {
     }
 }
 
+impl From<ConfigFileError> for ConfigError {
+    fn from(err: ConfigFileError) -> Self {
+        match err {
+            ConfigFileError::Io(err) => ConfigError::Io(err),
+            err => ConfigError::Location(err),
+        }
+    }
+}
+
+impl From<BackendError> for ConfigError {
+    fn from(err: BackendError) -> Self {
+        ConfigError::Verify(err)
+    }
+}
+
 impl fmt::Display for ConfigError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::ConfigError::*;
         match self {
             DeviceNotFound(device) => write!(f, "No devices found matching specifier {}", device),
+            MissingValue(field) => write!(f, "No {} given", field),
+            UnknownType(kind) => write!(f, "Unknown device type {}", kind),
+            InvalidSelector(selector) => write!(f, "Invalid selector {}", selector),
+            InvalidAddress(address) => write!(f, "Invalid address {}", address),
+            Duplicate(name) => write!(f, "A device named {} already exists", name),
+            RejectedSecret => write!(f, "The LIFX secret was rejected"),
+            Verify(err) => write!(f, "Couldn't verify the LIFX secret: {}", err),
             Io(err) => write!(f, "I/O error: {}", err),
+            Location(err) => write!(f, "{}", err),
+            Invalid(1) => write!(f, "The config file has an error"),
+            Invalid(errors) => write!(f, "The config file has {} errors", errors),
         }
     }
 }
@@ -100,10 +379,24 @@ impl ErrorT for ConfigError {}
 pub enum Error {
     /// An error encountered when using the `turn` subcommand.
     Turn(TurnError),
+    /// An error encountered when using the `set` subcommand.
+    Set(SetError),
+    /// An error encountered when using the `adjust`, `brighten`, or `dim` subcommands.
+    Adjust(AdjustError),
+    /// An error encountered when using the `effect` subcommand.
+    Effect(EffectError),
+    /// An error encountered when using the `at` subcommand.
+    At(AtError),
     /// An error encountered when using the `config` subcommand.
     Config(ConfigError),
     /// An error encountered when sending an MQTT message.
+    #[cfg(feature = "mqtt")]
     Send(SendError),
+    /// An error encountered when carrying out a command directly.
+    Execute(ExecuteError),
+    /// The requested mode wasn't compiled in.
+    #[allow(dead_code)]
+    Unsupported(&'static str),
 }
 
 impl From<TurnError> for Error {
@@ -112,12 +405,43 @@ impl From<TurnError> for Error {
     }
 }
 
+impl From<SetError> for Error {
+    fn from(err: SetError) -> Self {
+        Error::Set(err)
+    }
+}
+
+impl From<AdjustError> for Error {
+    fn from(err: AdjustError) -> Self {
+        Error::Adjust(err)
+    }
+}
+
+impl From<EffectError> for Error {
+    fn from(err: EffectError) -> Self {
+        Error::Effect(err)
+    }
+}
+
+impl From<AtError> for Error {
+    fn from(err: AtError) -> Self {
+        Error::At(err)
+    }
+}
+
 impl From<ConfigError> for Error {
     fn from(err: ConfigError) -> Self {
         Error::Config(err)
     }
 }
 
+impl From<ExecuteError> for Error {
+    fn from(err: ExecuteError) -> Self {
+        Error::Execute(err)
+    }
+}
+
+#[cfg(feature = "mqtt")]
 impl From<SendError> for Error {
     fn from(err: SendError) -> Self {
         Error::Send(err)
@@ -128,8 +452,15 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::Turn(err) => write!(f, "{}", err),
+            Error::Set(err) => write!(f, "{}", err),
+            Error::Adjust(err) => write!(f, "{}", err),
+            Error::Effect(err) => write!(f, "{}", err),
+            Error::At(err) => write!(f, "{}", err),
             Error::Config(err) => write!(f, "{}", err),
+            #[cfg(feature = "mqtt")]
             Error::Send(err) => write!(f, "{}", err),
+            Error::Execute(err) => write!(f, "{}", err),
+            Error::Unsupported(mode) => write!(f, "adm was built without {} support", mode),
         }
     }
 }
@@ -1,38 +1,194 @@
+use std::path::PathBuf;
+
 use adm::{
-    config::{MQTT_HOST, MQTT_PORT},
-    message::{Message, MqttMessage},
+    config::{Config, Mode, CONFIG},
+    message::Message,
 };
 use structopt::StructOpt;
 
-#[cfg(not(feature = "mqtt"))]
+#[cfg(not(any(feature = "mqtt", feature = "direct")))]
 compile_error!(
-    "mqtt is currently the only supported mode.\nPlease compile with the mqtt feature enabled."
+    "No modes are enabled.\nPlease compile with the mqtt and/or direct features enabled."
 );
-use rumqtt::*;
 // Sigh.
 use std::result::Result;
 
-const CLIENT_ID: &str = "adm-cli";
-
+mod adjust;
+mod at;
 mod config;
+#[cfg(feature = "direct")]
+mod direct;
+mod effect;
 mod error;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod prompt;
+mod set;
+mod status;
 mod turn;
 
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+struct Options {
+    /// The config file to use (overriding ADM_CONFIG and the default location).
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// The named profile to use, whose config is profiles/<name>.toml in the config directory.
+    ///
+    /// This can also be set with ADM_PROFILE.
+    #[structopt(long)]
+    profile: Option<String>,
+    /// Control devices directly instead of going through the MQTT broker.
+    ///
+    /// This can also be enabled by setting mode = "direct" in the config file.
+    #[structopt(long)]
+    direct: bool,
+    /// How long to wait for the MQTT daemon to acknowledge the command (e.g. 10s; 0 to not wait).
+    #[structopt(long)]
+    timeout: Option<String>,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
 enum Command {
     #[structopt(raw(setting = "structopt::clap::AppSettings::DisableVersion"))]
     /// Manage device power states.
     Turn {
-        /// The device to modify.
+        /// The devices to modify (a device, group, `all`, or a comma-separated list).
         device: String,
         /// The desired state of the device (on or off).
         state: String,
+        /// How long to fade over (e.g. 5s or 500ms).
+        #[structopt(long)]
+        duration: Option<String>,
     },
     /// Toggle device power states.
     Toggle {
-        /// The device to toggle.
+        /// The devices to toggle (a device, group, `all`, or a comma-separated list).
         device: String,
+        /// How long to fade over (e.g. 5s or 500ms).
+        #[structopt(long)]
+        duration: Option<String>,
+    },
+    /// Set device colors and brightnesses.
+    Set {
+        /// The devices to modify (a device, group, `all`, or a comma-separated list).
+        device: String,
+        /// The desired color (e.g. red, #ff8000, hue:120, or kelvin:2700).
+        #[structopt(long)]
+        color: Option<String>,
+        /// The desired brightness, as a percentage.
+        #[structopt(long)]
+        brightness: Option<String>,
+        /// How long to fade over (e.g. 5s or 500ms).
+        #[structopt(long)]
+        duration: Option<String>,
+    },
+    /// Make devices brighter.
+    Brighten {
+        /// The devices to brighten (a device, group, `all`, or a comma-separated list).
+        device: String,
+        /// How much to brighten by, as a percentage of full brightness.
+        #[structopt(default_value = "10%")]
+        amount: String,
+        /// How long to fade over (e.g. 5s or 500ms).
+        #[structopt(long)]
+        duration: Option<String>,
+    },
+    /// Make devices dimmer.
+    Dim {
+        /// The devices to dim (a device, group, `all`, or a comma-separated list).
+        device: String,
+        /// How much to dim by, as a percentage of full brightness.
+        #[structopt(default_value = "10%")]
+        amount: String,
+        /// How long to fade over (e.g. 5s or 500ms).
+        #[structopt(long)]
+        duration: Option<String>,
+    },
+    /// Change device colors and brightnesses relative to their current values.
+    Adjust {
+        /// The devices to modify (a device, group, `all`, or a comma-separated list).
+        device: String,
+        /// The change in brightness, as a percentage (e.g. +10% or -25%).
+        #[structopt(long, raw(allow_hyphen_values = "true"))]
+        brightness: Option<String>,
+        /// The change in hue, in degrees (e.g. +30).
+        #[structopt(long, raw(allow_hyphen_values = "true"))]
+        hue: Option<String>,
+        /// The change in saturation, as a percentage (e.g. -50%).
+        #[structopt(long, raw(allow_hyphen_values = "true"))]
+        saturation: Option<String>,
+        /// The change in color temperature, in Kelvin (e.g. -500 for warmer).
+        #[structopt(long, raw(allow_hyphen_values = "true"))]
+        kelvin: Option<String>,
+        /// How long to fade over (e.g. 5s or 500ms).
+        #[structopt(long)]
+        duration: Option<String>,
+    },
+    /// Run an effect (breathe, pulse, morph, or flame) on devices.
+    ///
+    /// Morph and flame are only supported by LIFX tiles.
+    Effect {
+        /// The devices to run the effect on (a device, group, `all`, or a comma-separated list).
+        device: String,
+        /// The effect to run (breathe, pulse, morph, or flame).
+        effect: String,
+        /// The color to cycle to (breathe and pulse only).
+        #[structopt(long)]
+        color: Option<String>,
+        /// The color to cycle from (breathe and pulse only; defaults to the current color).
+        #[structopt(long)]
+        from: Option<String>,
+        /// How long each cycle or movement takes (e.g. 2s).
+        #[structopt(long)]
+        period: Option<String>,
+        /// How many cycles to run (breathe and pulse only).
+        #[structopt(long)]
+        cycles: Option<u16>,
+        /// Stay at the effect's color once it finishes (breathe and pulse only).
+        #[structopt(long)]
+        persist: bool,
+        /// Where in each cycle the color peaks, from 0 to 1 (breathe and pulse only).
+        #[structopt(long)]
+        peak: Option<f32>,
+        /// The colors to drift through (morph only; up to 16, comma-separated).
+        #[structopt(long, raw(use_delimiter = "true"))]
+        palette: Vec<String>,
+        /// How long to run the effect for (morph and flame only; forever if omitted).
+        #[structopt(long)]
+        duration: Option<String>,
+    },
+    /// Activate a scene from the config file.
+    Scene {
+        /// The name of the scene.
+        name: String,
+    },
+    /// Show the current state of devices.
+    ///
+    /// Devices are queried directly, regardless of the configured mode.
+    Status {
+        /// The device to query (all devices are shown if omitted).
+        device: Option<String>,
+    },
+    /// Run a command later, at a time (e.g. 18:30 or 2019-12-24 18:00) or a time relative to
+    /// sunrise, sunset, dawn, or dusk (e.g. sunset-30m).
+    ///
+    /// Solar times require latitude and longitude to be set in the config file. adm waits in the
+    /// foreground until the time comes. --config and --profile must be given before `at`, not as
+    /// part of the command.
+    #[structopt(raw(
+        setting = "structopt::clap::AppSettings::TrailingVarArg",
+        setting = "structopt::clap::AppSettings::AllowLeadingHyphen"
+    ))]
+    At {
+        /// When to run the command.
+        time: String,
+        /// The command to run (e.g. turn porch on).
+        #[structopt(raw(required = "true"))]
+        command: Vec<String>,
     },
     /// Manage configuration settings/files.
     Config {
@@ -42,31 +198,128 @@ enum Command {
 }
 
 fn main() -> Result<(), error::Error> {
-    if let Some(message) = match Command::from_args() {
-        Command::Turn { device, state } => turn::turn(device, state)?,
-        Command::Toggle { device } => turn::toggle(device)?,
+    let options = Options::from_args();
+    if let Some(profile) = &options.profile {
+        Config::set_profile(profile.as_str());
+    }
+    if let Some(path) = &options.config {
+        Config::set_path(path.as_path());
+    }
+    run(options)
+}
+
+fn run(options: Options) -> Result<(), error::Error> {
+    if let Some(message) = match options.command {
+        Command::Turn {
+            device,
+            state,
+            duration,
+        } => turn::turn(device, state, duration)?,
+        Command::Toggle { device, duration } => turn::toggle(device, duration)?,
+        Command::Set {
+            device,
+            color,
+            brightness,
+            duration,
+        } => set::set(device, color, brightness, duration)?,
+        Command::Brighten {
+            device,
+            amount,
+            duration,
+        } => adjust::brighten(device, amount, false, duration)?,
+        Command::Dim {
+            device,
+            amount,
+            duration,
+        } => adjust::brighten(device, amount, true, duration)?,
+        Command::Adjust {
+            device,
+            brightness,
+            hue,
+            saturation,
+            kelvin,
+            duration,
+        } => adjust::adjust(device, brightness, hue, saturation, kelvin, duration)?,
+        Command::Effect {
+            device,
+            effect,
+            color,
+            from,
+            period,
+            cycles,
+            persist,
+            peak,
+            palette,
+            duration,
+        } => effect::effect(
+            device,
+            effect,
+            effect::Options {
+                color,
+                from,
+                period,
+                cycles,
+                persist,
+                peak,
+                palette,
+                duration,
+            },
+        )?,
+        Command::Scene { name } => match CONFIG.scene(&name) {
+            Some(_) => Some(Message::Scene { name }),
+            None => return Err(error::ExecuteError::SceneNotFound(name).into()),
+        },
+        Command::Status { device } => {
+            status::status(device)?;
+            None
+        }
+        Command::At { time, command } => {
+            let mut later = Options::from_iter(std::iter::once("adm".to_string()).chain(command));
+            // The config has already been loaded (to find the time), so it can't change now.
+            if later.config.is_some() {
+                return Err(error::AtError::LateOption("--config").into());
+            }
+            if later.profile.is_some() {
+                return Err(error::AtError::LateOption("--profile").into());
+            }
+            at::wait(&time, CONFIG.location())?;
+            later.direct |= options.direct;
+            later.timeout = later.timeout.or(options.timeout);
+            return run(later);
+        }
         Command::Config { command } => {
             config::config(command)?;
             None
         }
     } {
-        send(message)?;
+        let direct = options.direct || CONFIG.mode == Some(Mode::Direct);
+        if direct || cfg!(not(feature = "mqtt")) {
+            execute(message)?;
+        } else {
+            publish(message, options.timeout)?;
+        }
     }
     Ok(())
 }
 
-fn send(message: Message) -> Result<(), error::SendError> {
-    let message: MqttMessage = message.into();
-    let payload = message
-        .1
-        .and_then(|p| serde_json::to_string(&p).ok())
-        .unwrap_or_else(|| "".to_string());
-    let topic = message.0.as_str();
-    let opts = MqttOptions::new(CLIENT_ID, MQTT_HOST.to_string(), *MQTT_PORT);
-    if let Ok((mut client, rx)) = MqttClient::start(opts) {
-        client.subscribe(topic, QoS::AtLeastOnce)?;
-        client.publish(topic, QoS::ExactlyOnce, payload)?;
-        let _ = rx.recv();
-    }
+#[cfg(feature = "direct")]
+fn execute(message: Message) -> Result<(), error::Error> {
+    direct::execute(message)?;
     Ok(())
 }
+
+#[cfg(not(feature = "direct"))]
+fn execute(_: Message) -> Result<(), error::Error> {
+    Err(error::Error::Unsupported("direct"))
+}
+
+#[cfg(feature = "mqtt")]
+fn publish(message: Message, timeout: Option<String>) -> Result<(), error::Error> {
+    mqtt::send(message, timeout)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn publish(_: Message, _: Option<String>) -> Result<(), error::Error> {
+    Err(error::Error::Unsupported("mqtt"))
+}
@@ -0,0 +1,91 @@
+//! Sending commands via the MQTT broker.
+use std::{
+    process,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use adm::{
+    config::{MQTT_HOST, MQTT_PORT},
+    message::{Message, MqttMessage, MqttReply, Reply, Request},
+    parse,
+};
+use rumqtt::*;
+
+use crate::error::SendError;
+
+const CLIENT_ID: &str = "adm-cli";
+
+/// The prefix of the topics on which replies are requested.
+const REPLY_PREFIX: &str = "adm/replies";
+
+/// How long to wait for the daemon to acknowledge a command, if unspecified.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Generates an identifier unique to this invocation.
+fn correlation_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{}-{}", process::id(), nanos)
+}
+
+/// Publishes a command, then waits for the daemon to acknowledge it.
+///
+/// A timeout of zero publishes the command without waiting for a reply.
+pub fn send(message: Message, timeout: Option<String>) -> Result<(), SendError> {
+    let timeout = match timeout {
+        Some(t) => parse::duration(&t).ok_or(SendError::InvalidTimeout(t))?,
+        None => DEFAULT_TIMEOUT,
+    };
+    let (topic, payload): MqttMessage = message.into();
+    let id = correlation_id();
+    let reply = if timeout > Duration::from_secs(0) {
+        Some(Reply {
+            reply_to: format!("{}/{}", REPLY_PREFIX, id),
+            correlation_id: id.clone(),
+        })
+    } else {
+        None
+    };
+    let request = Request {
+        payload,
+        reply: reply.clone(),
+    }
+    .encode()?;
+    // Each invocation needs its own client ID, or concurrent invocations would disconnect each
+    // other while waiting for their replies.
+    let client_id = format!("{}-{}", CLIENT_ID, id);
+    let opts = MqttOptions::new(client_id, MQTT_HOST.to_string(), *MQTT_PORT);
+    let (mut client, rx) = MqttClient::start(opts)?;
+    let reply = match reply {
+        Some(reply) => reply,
+        None => {
+            client.publish(topic.as_str(), QoS::ExactlyOnce, false, request)?;
+            let _ = rx.recv();
+            return Ok(());
+        }
+    };
+    client.subscribe(reply.reply_to.as_str(), QoS::AtLeastOnce)?;
+    client.publish(topic.as_str(), QoS::ExactlyOnce, false, request)?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(remaining) {
+            Ok(Notification::Publish(publish)) if publish.topic_name == reply.reply_to => {
+                let response = match serde_json::from_slice::<MqttReply>(&publish.payload) {
+                    Ok(response) if response.correlation_id == reply.correlation_id => response,
+                    _ => continue,
+                };
+                return if response.success {
+                    Ok(())
+                } else {
+                    Err(SendError::Failed(response.errors))
+                };
+            }
+            Ok(_) => continue,
+            Err(err) if err.is_timeout() => return Err(SendError::Timeout(timeout)),
+            Err(_) => return Err(SendError::Disconnected),
+        }
+    }
+}
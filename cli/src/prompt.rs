@@ -0,0 +1,39 @@
+//! Interactive prompts.
+use std::io::{self, BufRead, Write};
+
+fn nonempty(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() {
+        None
+    } else {
+        Some(line.to_string())
+    }
+}
+
+/// Reads a line from standard input without prompting, returning `None` if it was empty.
+pub fn line() -> io::Result<Option<String>> {
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(nonempty(&line))
+}
+
+/// Prompts the user for a line of input, returning `None` if nothing was entered.
+pub fn optional(message: &str) -> io::Result<Option<String>> {
+    print!("{}: ", message);
+    io::stdout().flush()?;
+    line()
+}
+
+/// Prompts the user for a secret on the terminal, without echoing it.
+pub fn secret(message: &str) -> io::Result<Option<String>> {
+    let secret = rpassword::read_password_from_tty(Some(&format!("{}: ", message)))?;
+    Ok(nonempty(&secret))
+}
+
+/// Uses the given value if present, prompting the user for one otherwise.
+pub fn or_prompt(value: Option<String>, message: &str) -> io::Result<Option<String>> {
+    match value {
+        Some(value) => Ok(Some(value)),
+        None => optional(message),
+    }
+}
@@ -0,0 +1,42 @@
+use crate::error::SetError;
+use adm::{message::Message, parse};
+
+pub fn set(
+    device: String,
+    color: Option<String>,
+    brightness: Option<String>,
+    duration: Option<String>,
+) -> Result<Option<Message>, SetError> {
+    let color = match color {
+        Some(c) => Some(parse::lifx_color(&c).ok_or(SetError::InvalidColor(c))?),
+        None => None,
+    };
+    let brightness = match brightness {
+        Some(b) => Some(parse::brightness(&b).ok_or(SetError::InvalidBrightness(b))?),
+        None => None,
+    };
+    let duration = match duration {
+        Some(d) => Some(parse::duration(&d).ok_or(SetError::InvalidDuration(d))?),
+        None => None,
+    };
+    let message = match (color, brightness) {
+        (Some(color), Some(brightness)) => Message::State {
+            device,
+            color: Some(color),
+            brightness: Some(brightness),
+            duration,
+        },
+        (Some(color), None) => Message::Color {
+            device,
+            color,
+            duration,
+        },
+        (None, Some(brightness)) => Message::Brightness {
+            device,
+            brightness,
+            duration,
+        },
+        (None, None) => return Err(SetError::Empty),
+    };
+    Ok(Some(message))
+}
@@ -0,0 +1,116 @@
+use std::time::SystemTime;
+
+use adm::{
+    config::{CONFIG, CONTEXT},
+    device::{Device, DeviceState},
+};
+
+use crate::error::ExecuteError;
+
+const HEADINGS: [&str; 7] = [
+    "#",
+    "Name",
+    "Type",
+    "Power",
+    "Brightness",
+    "Color",
+    "Last seen",
+];
+
+fn color(state: &DeviceState) -> String {
+    match (state.hue, state.saturation, state.kelvin) {
+        (_, Some(0.0), Some(k)) => format!("{}K", k),
+        (Some(h), Some(s), _) => format!("hue:{:.0} saturation:{:.0}%", h, s * 100.0),
+        _ => "-".to_string(),
+    }
+}
+
+fn last_seen(state: &DeviceState) -> String {
+    match state
+        .last_seen
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+    {
+        Some(ago) => match ago.as_secs() {
+            s if s < 60 => format!("{}s ago", s),
+            s if s < 60 * 60 => format!("{}m ago", s / 60),
+            s if s < 60 * 60 * 24 => format!("{}h ago", s / 60 / 60),
+            s => format!("{}d ago", s / 60 / 60 / 24),
+        },
+        None => "-".to_string(),
+    }
+}
+
+fn row(index: usize, device: &Device) -> Vec<String> {
+    let mut row = vec![
+        (index + 1).to_string(),
+        device.name.clone(),
+        device.r#type.name().to_string(),
+    ];
+    match device.state(&CONTEXT) {
+        Ok(ref state) if state.reachable => {
+            row.push(match state.power {
+                Some(true) => "on".to_string(),
+                Some(false) => "off".to_string(),
+                None => "-".to_string(),
+            });
+            row.push(
+                state
+                    .brightness
+                    .map(|b| format!("{:.0}%", b * 100.0))
+                    .unwrap_or_else(|| "-".to_string()),
+            );
+            row.push(color(state));
+            row.push(last_seen(state));
+        }
+        Ok(ref state) => {
+            row.push("unreachable".to_string());
+            row.extend(vec!["-".to_string(); 2]);
+            row.push(last_seen(state));
+        }
+        Err(err) => {
+            row.push(format!("error: {}", err));
+            row.extend(vec!["-".to_string(); 3]);
+        }
+    }
+    row
+}
+
+/// Prints a table describing the state of the given device, or all devices if none is given.
+pub fn status(device: Option<String>) -> Result<(), ExecuteError> {
+    let rows = match device {
+        Some(name) => {
+            let device = CONFIG
+                .find(&name)
+                .ok_or(ExecuteError::DeviceNotFound(name))?;
+            let index = CONFIG
+                .devices
+                .iter()
+                .position(|d| d == device)
+                .unwrap_or_default();
+            vec![row(index, device)]
+        }
+        None => CONFIG
+            .devices
+            .iter()
+            .enumerate()
+            .map(|(index, device)| row(index, device))
+            .collect(),
+    };
+    let mut widths: Vec<usize> = HEADINGS.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let headings = HEADINGS.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+    for row in std::iter::once(&headings).chain(rows.iter()) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
@@ -1,5 +1,7 @@
+use std::time::Duration;
+
 use crate::error::TurnError;
-use adm::message::Message;
+use adm::{message::Message, parse};
 
 fn power_state<S: ToString>(s: &S) -> Option<bool> {
     let lower = s.to_string().to_ascii_lowercase();
@@ -10,16 +12,36 @@ fn power_state<S: ToString>(s: &S) -> Option<bool> {
     }
 }
 
-pub fn turn(device: String, state: String) -> Result<Option<Message>, TurnError> {
+fn duration(duration: Option<String>) -> Result<Option<Duration>, TurnError> {
+    match duration {
+        Some(d) => Ok(Some(
+            parse::duration(&d).ok_or(TurnError::InvalidDuration(d))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+pub fn turn(
+    device: String,
+    state: String,
+    fade: Option<String>,
+) -> Result<Option<Message>, TurnError> {
     if let Some(target) = power_state(&state) {
-        Ok(Some(Message::Power { device, target }))
+        Ok(Some(Message::Power {
+            device,
+            power: target,
+            duration: duration(fade)?,
+        }))
     } else if power_state(&device).is_some() {
-        turn(state, device)
+        turn(state, device, fade)
     } else {
         Err(TurnError::UnrecognizedState(state))
     }
 }
 
-pub fn toggle(device: String) -> Result<Option<Message>, TurnError> {
-    Ok(Some(Message::Toggle { device }))
+pub fn toggle(device: String, fade: Option<String>) -> Result<Option<Message>, TurnError> {
+    Ok(Some(Message::Toggle {
+        device,
+        duration: duration(fade)?,
+    }))
 }
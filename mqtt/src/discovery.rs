@@ -0,0 +1,130 @@
+//! [Home Assistant MQTT discovery](https://www.home-assistant.io/docs/mqtt/discovery/).
+//!
+//! Each configured device is announced as a light whose commands are sent to the usual
+//! `devices/<name>/…` topics, so it shows up in Home Assistant without any manual configuration.
+use adm::{
+    config::Config,
+    device::Device,
+    message::{state_topic, MqttPayload, PAYLOAD_VERSION},
+};
+use log::error;
+use rumqtt::{MqttClient, QoS};
+use serde_json::json;
+
+use crate::{reload, state::AVAILABILITY_TOPIC};
+
+/// The topic prefix Home Assistant listens for discovery documents on.
+const PREFIX: &str = "homeassistant";
+/// Prepended to object IDs so that our documents can be told apart from others.
+const ID_PREFIX: &str = "adm_";
+
+/// The topic filter matching every light discovery document.
+pub const TOPIC: &str = "homeassistant/light/+/config";
+
+/// Derives a discovery object ID from a device name.
+fn object_id(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}{}", ID_PREFIX, name)
+}
+
+fn config_topic(id: &str) -> String {
+    format!("{}/light/{}/config", PREFIX, id)
+}
+
+fn payload(payload: &MqttPayload) -> String {
+    payload.encode().unwrap_or_default()
+}
+
+/// Builds the discovery document for a device.
+fn document(device: &Device) -> serde_json::Value {
+    let id = object_id(&device.name);
+    let topic = |action: &str| format!("devices/{}/{}", device.name, action);
+    let on = payload(&MqttPayload::Power {
+        power: true,
+        duration: None,
+    });
+    let off = payload(&MqttPayload::Power {
+        power: false,
+        duration: None,
+    });
+    json!({
+        "name": device.name,
+        "unique_id": id,
+        "command_topic": topic("power"),
+        "payload_on": on,
+        "payload_off": off,
+        "state_topic": state_topic(&device.name),
+        "state_value_template": format!(
+            "{{% if value_json.power %}}{}{{% else %}}{}{{% endif %}}",
+            on, off
+        ),
+        "brightness_command_topic": topic("brightness"),
+        "brightness_command_template": format!(
+            "{{\"version\": {}, \"type\": \"state\", \"brightness\": {{{{ value / 255 }}}}}}",
+            PAYLOAD_VERSION
+        ),
+        "brightness_state_topic": state_topic(&device.name),
+        "brightness_value_template": "{{ (value_json.brightness * 255) | round(0) }}",
+        "hs_command_topic": topic("color"),
+        "hs_command_template": format!(
+            "{{\"version\": {}, \"type\": \"state\", \"color\": \"hue:{{{{ hue }}}} saturation:{{{{ sat / 100 }}}}\"}}",
+            PAYLOAD_VERSION
+        ),
+        "availability_topic": AVAILABILITY_TOPIC,
+        "payload_available": "online",
+        "payload_not_available": "offline",
+        "device": {
+            "identifiers": [id],
+            "name": device.name,
+            "model": device.r#type.name(),
+        },
+    })
+}
+
+/// Publishes (retained) discovery documents for all configured devices.
+pub fn announce(client: &mut MqttClient, config: &Config) {
+    for device in &config.devices {
+        let topic = config_topic(&object_id(&device.name));
+        let document = document(device).to_string();
+        if let Err(err) = client.publish(topic, QoS::AtLeastOnce, true, document) {
+            error!("Failed to announce {}: {}", device.name, err);
+        }
+    }
+}
+
+/// Whether a (retained) discovery document belongs to a device that is no longer configured.
+pub fn is_stale(topic: &str, payload: &[u8]) -> bool {
+    if payload.is_empty() {
+        return false;
+    }
+    let mut parts = topic.split('/');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(PREFIX), Some("light"), Some(id), Some("config")) => {
+            let context = reload::current();
+            let devices = &context.config().devices;
+            id.starts_with(ID_PREFIX) && !devices.iter().any(|d| object_id(&d.name) == id)
+        }
+        _ => false,
+    }
+}
+
+/// Removes the discovery document of a device which is no longer configured.
+pub fn retract(client: &mut MqttClient, device: &Device) {
+    remove(client, &config_topic(&object_id(&device.name)));
+}
+
+/// Removes a stale discovery document by clearing its retained message.
+pub fn remove(client: &mut MqttClient, topic: &str) {
+    if let Err(err) = client.publish(topic, QoS::AtLeastOnce, true, Vec::new()) {
+        error!("Failed to remove discovery document {}: {}", topic, err);
+    }
+}
@@ -1,13 +1,37 @@
-use adm::lifxi::http::Error as LifxiError;
 use adm::{
-    config::{CONFIG, MQTT_HOST, MQTT_PORT},
-    message::MqttPayload,
+    config::Config,
+    message::{
+        error_topic, ErrorKind, Message, MqttError, MqttMessage, MqttPayload, MqttReply, Request,
+    },
+    rule::Engine,
+    scene::Error as SceneError,
 };
+use chrono::Local;
+use log::{error, info};
 use rumqtt::{error::ConnectError, *};
-use std::result::Result;
+use std::{path::PathBuf, result::Result};
+use structopt::StructOpt;
+
+mod discovery;
+mod reload;
+mod schedule;
+mod state;
 
 const CLIENT_ID: &str = "adm-client";
 
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+struct Options {
+    /// The config file to use (overriding ADM_CONFIG and the default location).
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// The named profile to use, whose config is profiles/<name>.toml in the config directory.
+    ///
+    /// This can also be set with ADM_PROFILE.
+    #[structopt(long)]
+    profile: Option<String>,
+}
+
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
 pub enum Error {
@@ -17,8 +41,8 @@ pub enum Error {
     Subscribe(ConnectError),
     /// An error was encountered while polling for messages.
     Poll,
-    /// An error occured while modifying the device power status.
-    Power(LifxiError),
+    /// An error was encountered while publishing the client's availability.
+    Publish(ClientError),
 }
 
 impl From<ClientError> for Error {
@@ -27,12 +51,6 @@ impl From<ClientError> for Error {
     }
 }
 
-impl From<LifxiError> for Error {
-    fn from(err: LifxiError) -> Self {
-        Error::Power(err)
-    }
-}
-
 impl From<ConnectError> for Error {
     fn from(err: ConnectError) -> Self {
         Error::Subscribe(err)
@@ -45,13 +63,16 @@ enum Route {
     Brightness(String),
     Color(String),
     State(String),
+    Adjust(String),
+    Effect(String),
+    Scene(String),
 }
 
 impl Route {
     fn try_parse(s: &str) -> Option<Self> {
         use self::Route::*;
         let mut parts = s.split('/');
-        parts.next().and_then(|root| match root {
+        let route = parts.next().and_then(|root| match root {
             "devices" => parts.next().and_then(|device| {
                 parts.next().and_then(|action| {
                     let device = device.to_string();
@@ -64,12 +85,211 @@ impl Route {
                         "brightness" => Some(Brightness(device)),
                         "color" => Some(Color(device)),
                         "state" => Some(State(device)),
+                        "adjust" => Some(Adjust(device)),
+                        "effect" => Some(Effect(device)),
                         _ => None,
                     }
                 })
             }),
+            "scenes" => parts.next().and_then(|scene| match parts.next() {
+                Some("activate") => Some(Scene(scene.to_string())),
+                _ => None,
+            }),
             _ => None,
-        })
+        });
+        // Reject anything with trailing segments (e.g. devices/+/state/current).
+        route.filter(|_| parts.next().is_none())
+    }
+    /// Interprets the payload received on this route as a message.
+    ///
+    /// Returns `None` if the payload doesn't belong on this route.
+    fn message(self, payload: Option<MqttPayload>) -> Option<Message> {
+        use self::Route::*;
+        match (self, payload) {
+            (Power(device), Some(MqttPayload::Power { power, duration })) => Some(Message::Power {
+                device,
+                power,
+                duration,
+            }),
+            (Toggle(device), Some(MqttPayload::State { duration, .. })) => {
+                Some(Message::Toggle { device, duration })
+            }
+            (Toggle(device), _) => Some(Message::Toggle {
+                device,
+                duration: None,
+            }),
+            (Scene(name), _) => Some(Message::Scene { name }),
+            (Adjust(device), Some(MqttPayload::Adjust { delta, duration })) => {
+                Some(Message::Adjust {
+                    device,
+                    delta,
+                    duration,
+                })
+            }
+            (Effect(device), Some(MqttPayload::Effect(effect))) => {
+                Some(Message::Effect { device, effect })
+            }
+            (
+                Brightness(device),
+                Some(MqttPayload::State {
+                    brightness,
+                    duration,
+                    ..
+                }),
+            ) => Some(Message::State {
+                device,
+                color: None,
+                brightness,
+                duration,
+            }),
+            (
+                Color(device),
+                Some(MqttPayload::State {
+                    color, duration, ..
+                }),
+            ) => Some(Message::State {
+                device,
+                color,
+                brightness: None,
+                duration,
+            }),
+            (
+                State(device),
+                Some(MqttPayload::State {
+                    color,
+                    brightness,
+                    duration,
+                }),
+            ) => Some(Message::State {
+                device,
+                color,
+                brightness,
+                duration,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Publishes a message to the broker, to be handled like any other incoming command.
+fn dispatch(client: &mut MqttClient, message: Message) {
+    let (topic, payload): MqttMessage = message.into();
+    let payload = payload.and_then(|p| p.encode().ok()).unwrap_or_default();
+    if let Err(err) = client.publish(topic.as_str(), QoS::ExactlyOnce, false, payload) {
+        error!("Failed to publish message to {}: {}", topic, err);
+    }
+}
+
+/// Logs a failed command and publishes the failure to the command's error topic.
+fn report(client: &mut MqttClient, failure: &MqttError) {
+    error!("Command to {} failed: {}", failure.topic, failure);
+    let topic = match error_topic(&failure.topic) {
+        Some(topic) => topic,
+        None => return,
+    };
+    match serde_json::to_string(failure) {
+        Ok(payload) => {
+            if let Err(err) = client.publish(topic.as_str(), QoS::AtLeastOnce, false, payload) {
+                error!("Failed to publish error to {}: {}", topic, err);
+            }
+        }
+        Err(err) => error!("Failed to serialize error: {}", err),
+    }
+}
+
+/// Carries out a command received on the given topic, collecting any failures.
+fn execute(
+    client: &mut MqttClient,
+    topic: &str,
+    route: Route,
+    payload: Option<MqttPayload>,
+) -> Vec<MqttError> {
+    let failure = |kind, device: Option<&str>, message: String| MqttError {
+        topic: topic.to_string(),
+        kind,
+        device: device.map(str::to_string),
+        message,
+    };
+    let message = match route.message(payload.clone()) {
+        Some(message) => message,
+        None => {
+            let message = format!("Unexpected payload {:?}", payload);
+            return vec![failure(ErrorKind::UnexpectedPayload, None, message)];
+        }
+    };
+    let mut failures = Vec::new();
+    let context = reload::current();
+    if let Message::Scene { name } = &message {
+        let scene = match context.config().scene(name) {
+            Some(scene) => scene,
+            None => {
+                let message = format!("No scene named {}", name);
+                return vec![failure(ErrorKind::SceneNotFound, None, message)];
+            }
+        };
+        let applied = scene.apply(&context, false);
+        for device in applied.applied {
+            state::publish(client, &context, device);
+        }
+        for (device, err) in applied.failures {
+            let kind = match err {
+                SceneError::DeviceNotFound(_) => ErrorKind::DeviceNotFound,
+                SceneError::InvalidColor(_)
+                | SceneError::InvalidBrightness(_)
+                | SceneError::InvalidDuration(_) => ErrorKind::InvalidScene,
+                SceneError::Backend(_) => ErrorKind::Device,
+            };
+            failures.push(failure(kind, Some(&device), err.to_string()));
+        }
+        return failures;
+    }
+    let specifier = message.device().unwrap_or_default();
+    let devices = context.config().resolve(specifier);
+    if devices.is_empty() {
+        let message = format!("No devices found matching specifier {}", specifier);
+        return vec![failure(ErrorKind::DeviceNotFound, None, message)];
+    }
+    for device in devices {
+        if let Err(err) = message.clone().apply(&context, device, false) {
+            failures.push(failure(
+                ErrorKind::Device,
+                Some(&device.name),
+                err.to_string(),
+            ));
+        }
+        state::publish(client, &context, device);
+    }
+    failures
+}
+
+/// Handles a request received on the given topic, reporting any failures and acknowledging it if
+/// the publisher asked for a reply.
+fn handle(client: &mut MqttClient, topic: &str, route: Route, payload: &str) {
+    let request = match Request::decode(payload) {
+        Ok(request) => request,
+        Err(err) => {
+            let failure = MqttError {
+                topic: topic.to_string(),
+                kind: ErrorKind::InvalidPayload,
+                device: None,
+                message: err.to_string(),
+            };
+            return report(client, &failure);
+        }
+    };
+    let failures = execute(client, topic, route, request.payload);
+    for failure in &failures {
+        report(client, failure);
+    }
+    if let Some(reply) = request.reply {
+        let payload = match serde_json::to_string(&MqttReply::new(&reply, failures)) {
+            Ok(payload) => payload,
+            Err(err) => return error!("Failed to serialize reply: {}", err),
+        };
+        let topic = reply.reply_to.as_str();
+        if let Err(err) = client.publish(topic, QoS::AtLeastOnce, false, payload) {
+            error!("Failed to publish reply to {}: {}", topic, err);
+        }
     }
 }
 
@@ -79,72 +299,86 @@ const TOPICS: &[&str] = &[
     "devices/+/brightness",
     "devices/+/color",
     "devices/+/state",
+    "devices/+/adjust",
+    "devices/+/effect",
+    "scenes/+/activate",
 ];
 
 fn main() -> Result<(), Error> {
-    let opts = MqttOptions::new(CLIENT_ID, MQTT_HOST.to_string(), *MQTT_PORT);
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let options = Options::from_args();
+    if let Some(profile) = &options.profile {
+        Config::set_profile(profile.as_str());
+    }
+    if let Some(path) = &options.config {
+        Config::set_path(path.as_path());
+    }
+    if let Ok(path) = Config::path() {
+        info!("Using config file {}", path.display());
+    }
+    // Daemons for different profiles (e.g. two houses) may share a broker, so they need distinct
+    // client IDs.
+    let client_id = match &options.profile {
+        Some(profile) => format!("{}-{}", CLIENT_ID, profile),
+        None => CLIENT_ID.to_string(),
+    };
+    let will = LastWill {
+        topic: state::AVAILABILITY_TOPIC.to_string(),
+        message: "offline".to_string(),
+        qos: QoS::AtLeastOnce,
+        retain: true,
+    };
+    // The context the daemon starts with; only devices, groups, scenes, and the LIFX secret are
+    // reloaded later.
+    let context = reload::current();
+    let config = context.config();
+    let host = context
+        .mqtt_host()
+        .expect("MQTT mode used but no host configured.");
+    let port = context.mqtt_port();
+    let opts = MqttOptions::new(client_id, host, port).set_last_will(will);
     let (mut client, rx) = MqttClient::start(opts)?;
     for topic in TOPICS {
         client.subscribe(*topic, QoS::ExactlyOnce)?;
     }
+    client
+        .publish(state::AVAILABILITY_TOPIC, QoS::AtLeastOnce, true, "online")
+        .map_err(Error::Publish)?;
+    discovery::announce(&mut client, config);
+    client.subscribe(discovery::TOPIC, QoS::AtLeastOnce)?;
+    let mut rules = match Engine::new(config.rules.as_deref().unwrap_or(&[]), config) {
+        Ok(engine) => Some(engine),
+        Err(err) => {
+            error!("Invalid rule: {}", err);
+            None
+        }
+    };
+    for topic in rules.iter().flat_map(|engine| engine.topics()) {
+        client.subscribe(topic, QoS::AtLeastOnce)?;
+    }
+    info!("Listening for commands on {}:{}", host, port);
+    state::poll(client.clone(), config);
+    schedule::run(client.clone(), config);
+    reload::watch(client.clone());
+    reload::on_hangup(client.clone());
     while let Ok(message) = rx.recv() {
         if let Notification::Publish(body) = message {
             let topic = body.topic_name;
             let payload = body.payload.to_vec();
+            if discovery::is_stale(&topic, &payload) {
+                discovery::remove(&mut client, &topic);
+                continue;
+            }
             if let Ok(payload) = String::from_utf8(payload) {
-                if let Some(route) = Route::try_parse(&topic) {
-                    match route {
-                        Route::Power(device) => {
-                            if let Some(device) = CONFIG.find(&device) {
-                                if let Ok(payload) = serde_json::from_str(&payload) {
-                                    if let MqttPayload::Power { power } = payload {
-                                        device.power(power, true)?;
-                                    }
-                                }
-                            }
-                        }
-                        Route::Toggle(device) => {
-                            if let Some(device) = CONFIG.find(&device) {
-                                device.toggle()?;
-                            }
-                        }
-                        Route::Brightness(device) => {
-                            if let Some(device) = CONFIG.find(&device) {
-                                if let Ok(payload) = serde_json::from_str(&payload) {
-                                    if let MqttPayload::State {
-                                        brightness,
-                                        color: _,
-                                    } = payload
-                                    {
-                                        device.set(None, brightness, true)?;
-                                    }
-                                }
-                            }
-                        }
-                        Route::Color(device) => {
-                            if let Some(device) = CONFIG.find(&device) {
-                                if let Ok(payload) = serde_json::from_str(&payload) {
-                                    if let MqttPayload::State {
-                                        color,
-                                        brightness: _,
-                                    } = payload
-                                    {
-                                        device.set(color, None, true)?;
-                                    }
-                                }
-                            }
-                        }
-                        Route::State(device) => {
-                            if let Some(device) = CONFIG.find(&device) {
-                                if let Ok(payload) = serde_json::from_str(&payload) {
-                                    if let MqttPayload::State { color, brightness } = payload {
-                                        device.set(color, brightness, true)?;
-                                    }
-                                }
-                            }
-                        }
+                if let Some(engine) = &mut rules {
+                    let now = Local::now().time();
+                    for message in engine.evaluate(&topic, &payload, now, state::power) {
+                        dispatch(&mut client, message);
                     }
                 }
+                if let Some(route) = Route::try_parse(&topic) {
+                    handle(&mut client, &topic, route, &payload);
+                }
             }
         }
     }
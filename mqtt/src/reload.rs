@@ -0,0 +1,206 @@
+//! Reloading the config file while the client runs.
+//!
+//! The config is reloaded whenever the file changes or the client receives `SIGHUP`. A new config
+//! is only swapped in once it parses and its rules and schedules are valid; otherwise the old one
+//! stays live. Devices, groups, scenes, and the LIFX secret take effect immediately, while the
+//! broker connection, rules, schedules, and polling interval are fixed at startup.
+use std::{
+    fmt::Display,
+    path::PathBuf,
+    sync::{mpsc::channel, Arc, Mutex, PoisonError, RwLock},
+    thread,
+    time::Duration,
+};
+
+use adm::{
+    config::{Config, CONFIG},
+    rule::Engine,
+    schedule::{Scheduler, SystemClock},
+    Context,
+};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use rumqtt::MqttClient;
+
+use crate::discovery;
+
+/// How long to wait for a burst of file events (e.g. an editor's save) to settle.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    /// The live context, replaced whenever the config file is reloaded successfully.
+    static ref CURRENT: RwLock<Arc<Context>> = RwLock::new(Arc::new(Context::new(CONFIG.clone())));
+    /// Held while reloading, so that a file change and a signal don't race each other.
+    static ref RELOADING: Mutex<()> = Mutex::new(());
+}
+
+/// The live context.
+///
+/// Callers should fetch it once per command, so that a reload midway through doesn't mix devices
+/// from two configs.
+pub fn current() -> Arc<Context> {
+    CURRENT
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Checks the parts of a config which are only interpreted once it's in use.
+fn validate(config: &Config) -> Result<(), String> {
+    Engine::new(config.rules.as_deref().unwrap_or(&[]), config)
+        .map_err(|err| format!("invalid rule: {}", err))?;
+    Scheduler::new(
+        config.schedules.as_deref().unwrap_or(&[]),
+        config.location(),
+        SystemClock,
+    )
+    .map_err(|err| format!("invalid schedule: {}", err))?;
+    Ok(())
+}
+
+/// Logs the entries which were added, removed, or changed between two lists, matched by name.
+fn log_changes<T: PartialEq>(kind: &str, old: &[T], new: &[T], name: impl Fn(&T) -> &str) {
+    for entry in new {
+        match old.iter().find(|o| name(o) == name(entry)) {
+            None => info!("Added {} {}", kind, name(entry)),
+            Some(o) if o != entry => info!("Changed {} {}", kind, name(entry)),
+            Some(_) => {}
+        }
+    }
+    for entry in old {
+        if !new.iter().any(|n| name(n) == name(entry)) {
+            info!("Removed {} {}", kind, name(entry));
+        }
+    }
+}
+
+/// Warns about changes to a setting which is only read at startup.
+fn log_fixed<T: PartialEq>(setting: &str, old: T, new: T) {
+    if old != new {
+        warn!("Changes to {} take effect after a restart", setting);
+    }
+}
+
+/// Logs the differences between the live config and a new one.
+fn log_diff(old: &Config, new: &Config) {
+    log_changes("device", &old.devices, &new.devices, |d| &d.name);
+    log_changes(
+        "group",
+        old.groups.as_deref().unwrap_or(&[]),
+        new.groups.as_deref().unwrap_or(&[]),
+        |g| &g.name,
+    );
+    log_changes(
+        "scene",
+        old.scenes.as_deref().unwrap_or(&[]),
+        new.scenes.as_deref().unwrap_or(&[]),
+        |s| &s.name,
+    );
+    if old.lifx_secret != new.lifx_secret {
+        info!("Changed the LIFX secret");
+    }
+    log_fixed(
+        "the MQTT broker",
+        (&old.mqtt_host, old.mqtt_port),
+        (&new.mqtt_host, new.mqtt_port),
+    );
+    log_fixed("the state interval", old.state_interval, new.state_interval);
+    log_fixed("rules", &old.rules, &new.rules);
+    log_fixed(
+        "schedules",
+        (&old.schedules, old.location()),
+        (&new.schedules, new.location()),
+    );
+}
+
+/// Rereads the config file, swapping it in if it's valid.
+///
+/// Discovery documents are republished so that Home Assistant sees added and removed devices.
+pub fn reload(client: &mut MqttClient, reason: impl Display) {
+    let _reloading = RELOADING.lock().unwrap_or_else(PoisonError::into_inner);
+    info!("Reloading the config file ({})", reason);
+    let config = match Config::parse() {
+        Ok(config) => config,
+        Err(err) => return error!("Keeping the current config: {}", err),
+    };
+    if let Err(err) = validate(&config) {
+        return error!("Keeping the current config: {}", err);
+    }
+    let old = current();
+    log_diff(old.config(), &config);
+    let new = Arc::new(Context::new(config));
+    *CURRENT.write().unwrap_or_else(PoisonError::into_inner) = new.clone();
+    for device in &old.config().devices {
+        if new.config().find(&device.name).is_none() {
+            discovery::retract(client, device);
+        }
+    }
+    discovery::announce(client, new.config());
+}
+
+/// Whether a file event touches the given path.
+fn affects(event: &DebouncedEvent, path: &PathBuf) -> bool {
+    use self::DebouncedEvent::*;
+    match event {
+        Create(p) | Write(p) | Chmod(p) => p == path,
+        Rename(from, to) => from == path || to == path,
+        _ => false,
+    }
+}
+
+/// Reloads the config whenever the file changes, in the background.
+///
+/// The file's directory is watched rather than the file itself, since editors often save by
+/// replacing the file.
+pub fn watch(mut client: MqttClient) {
+    let path = match Config::path() {
+        Ok(path) => path,
+        Err(err) => return error!("Not watching the config file: {}", err),
+    };
+    let directory = match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(directory) => directory.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+    let (tx, rx) = channel();
+    let mut watcher = match notify::watcher(tx, DEBOUNCE) {
+        Ok(watcher) => watcher,
+        Err(err) => return error!("Failed to watch the config file: {}", err),
+    };
+    if let Err(err) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+        return error!("Failed to watch {}: {}", directory.display(), err);
+    }
+    // Event paths are absolute, so compare against the file's canonical location.
+    let path = directory
+        .canonicalize()
+        .map(|d| d.join(path.file_name().unwrap_or_default()))
+        .unwrap_or(path);
+    thread::spawn(move || {
+        // The watcher stops when dropped, so it lives as long as the thread.
+        let _watcher = watcher;
+        for event in rx {
+            if affects(&event, &path) {
+                reload(&mut client, "file changed");
+            }
+        }
+    });
+}
+
+/// Reloads the config whenever the client receives `SIGHUP`, in the background.
+#[cfg(unix)]
+pub fn on_hangup(mut client: MqttClient) {
+    use signal_hook::{iterator::Signals, SIGHUP};
+    let signals = match Signals::new([SIGHUP]) {
+        Ok(signals) => signals,
+        Err(err) => return error!("Failed to listen for SIGHUP: {}", err),
+    };
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            reload(&mut client, "SIGHUP");
+        }
+    });
+}
+
+/// Signals aren't available, so only file changes trigger reloads.
+#[cfg(not(unix))]
+pub fn on_hangup(_client: MqttClient) {}
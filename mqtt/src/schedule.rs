@@ -0,0 +1,27 @@
+//! Running configured schedules.
+use std::thread;
+
+use adm::{
+    config::Config,
+    schedule::{Scheduler, SystemClock},
+};
+use log::error;
+use rumqtt::MqttClient;
+
+use crate::dispatch;
+
+/// Runs the schedules in the given config in the background.
+pub fn run(mut client: MqttClient, config: &Config) {
+    let schedules = match &config.schedules {
+        Some(schedules) if !schedules.is_empty() => schedules,
+        _ => return,
+    };
+    let scheduler = match Scheduler::new(schedules, config.location(), SystemClock) {
+        Ok(scheduler) => scheduler,
+        Err(err) => {
+            error!("Invalid schedule: {}", err);
+            return;
+        }
+    };
+    thread::spawn(move || scheduler.run(|message| dispatch(&mut client, message)));
+}
@@ -0,0 +1,60 @@
+//! Publication of device states.
+use std::{thread, time::Duration};
+
+use adm::{
+    config::Config,
+    device::Device,
+    message::{state_topic, MqttState},
+    Context,
+};
+use log::error;
+use rumqtt::{MqttClient, QoS};
+
+/// The topic on which the client announces whether it's online (retained).
+pub const AVAILABILITY_TOPIC: &str = "adm/availability";
+
+use crate::reload;
+
+/// Queries the state of a device and publishes it (retained) to its state topic.
+pub fn publish(client: &mut MqttClient, context: &Context, device: &Device) {
+    let state = match device.state(context) {
+        Ok(state) => state,
+        Err(err) => {
+            error!("Failed to query the state of {}: {}", device.name, err);
+            return;
+        }
+    };
+    if let Ok(payload) = serde_json::to_string(&MqttState::from(&state)) {
+        let topic = state_topic(&device.name);
+        if let Err(err) = client.publish(topic, QoS::AtLeastOnce, true, payload) {
+            error!("Failed to publish the state of {}: {}", device.name, err);
+        }
+    }
+}
+
+/// Queries whether a device is on, for checking rule conditions.
+pub fn power(device: &Device) -> Option<bool> {
+    match device.state(&reload::current()) {
+        Ok(state) => state.power,
+        Err(err) => {
+            error!("Failed to query the state of {}: {}", device.name, err);
+            None
+        }
+    }
+}
+
+/// Periodically publishes the states of all configured devices in the background, at the interval
+/// set in the given config.
+pub fn poll(mut client: MqttClient, config: &Config) {
+    let interval = config.state_interval.unwrap_or(60);
+    if interval == 0 {
+        return;
+    }
+    thread::spawn(move || loop {
+        let context = reload::current();
+        for device in &context.config().devices {
+            publish(&mut client, &context, device);
+        }
+        thread::sleep(Duration::from_secs(interval));
+    });
+}